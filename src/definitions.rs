@@ -1,21 +1,355 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
+
+use rusoto_core::{credential::StaticProvider, Region};
+
+use crate::sql::{ErrorPolicy, FileSinkFormat, Format, SocketProtocol, Watermark};
 
 #[derive(Debug, Clone)]
 pub struct Catalog {
     pub relations: HashMap<String, RelationDefinition>,
     pub functions: HashMap<String, FunctionDefinition>,
+    pub output_format: OutputFormat,
+}
+
+/// How SELECT prints records to stdout, set by `SET output_format = ...` or the
+/// `--output-format` flag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// CSV with a header line naming the columns of the first record.
+    Csv,
+    /// Columns aligned to the width of the names and values of the first record.
+    Table,
+    /// Indented JSON objects.
+    PrettyJson,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat, String> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "table" => Ok(OutputFormat::Table),
+            "pretty_json" => Ok(OutputFormat::PrettyJson),
+            _ => Err(format!(
+                "output_format needs to be JSON, CSV, TABLE or PRETTY_JSON, not {name}"
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum RelationDefinition {
-    KinesisStream(KinesisStream),
+    KinesisStream(Box<KinesisStream>),
+    KafkaTopic(Box<KafkaTopic>),
+    FileSource(FileSource),
+    FileSink(FileSink),
+    SocketSource(SocketSource),
+    HttpSource(HttpSource),
+    GeneratorSource(GeneratorSource),
+    /// The built-in `stdin` relation, with a record decoded from every line of standard input.
+    Stdin,
 }
 
 #[derive(Debug, Clone)]
 pub struct KinesisStream {
     pub kinesis_stream_name: String,
+    #[allow(dead_code)]
     pub kinesis_stream_arn: String,
-    pub kinesis_stream_consumer_arn: String,
+    pub read_mode: KinesisReadMode,
+    pub format: Format,
+    pub error_policy: ErrorPolicy,
+    pub region: Region,
+    /// Credentials given in the WITH clause; without them the default chain of environment,
+    /// profile and instance metadata credentials is used.
+    pub credentials: Option<StaticProvider>,
+    pub starting_position: StartingPosition,
+    /// Deregister the enhanced fan-out consumer when the relation is dropped.
+    pub deregister_consumer: bool,
+    pub watermark: Option<Watermark>,
+}
+
+/// How the shards of a Kinesis stream are read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KinesisReadMode {
+    /// Records are pushed to the enhanced fan-out consumer with the ARN.
+    EnhancedFanOut { consumer_arn: String },
+    /// GetRecords is called for every shard every `poll_interval` milliseconds, for up to
+    /// `max_records` records, sharing the read throughput of the stream with other consumers.
+    Polling {
+        poll_interval: u64,
+        max_records: i64,
+    },
+}
+
+/// Where reading the shards of a Kinesis stream starts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum StartingPosition {
+    /// The oldest record still retained.
+    #[default]
+    TrimHorizon,
+    /// Only records added after subscribing.
+    Latest,
+    /// The first record at or after the time, in milliseconds since the epoch.
+    AtTimestamp(i64),
+    /// The record with the given sequence number, by shard ID. Shards without one start at the
+    /// trim horizon.
+    AtSequenceNumber(Vec<(String, String)>),
+}
+
+impl StartingPosition {
+    /// Reads the `start`, `start_timestamp` or `start_sequence_number` option of a Kinesis
+    /// stream, returning `None` for any other option.
+    pub fn from_option(key: &str, value: &str) -> Option<Result<StartingPosition, String>> {
+        match key {
+            "start" => Some(match value.to_uppercase().as_str() {
+                "TRIM_HORIZON" => Ok(StartingPosition::TrimHorizon),
+                "LATEST" => Ok(StartingPosition::Latest),
+                _ => Err(format!(
+                    "start needs to be TRIM_HORIZON or LATEST, not {value}"
+                )),
+            }),
+            "start_timestamp" => Some(
+                chrono::DateTime::parse_from_rfc3339(value)
+                    .map(|timestamp| StartingPosition::AtTimestamp(timestamp.timestamp_millis()))
+                    .map_err(|error| format!("invalid start_timestamp {value}: {error}")),
+            ),
+            // Sequence numbers only exist within a shard, so they are given as
+            // `shard_id:sequence_number`, separated by commas.
+            "start_sequence_number" => Some(
+                value
+                    .split(',')
+                    .map(|shard_sequence_number| {
+                        shard_sequence_number
+                            .trim()
+                            .split_once(':')
+                            .map(|(shard_id, sequence_number)| {
+                                (shard_id.to_string(), sequence_number.to_string())
+                            })
+                            .ok_or_else(|| {
+                                format!("invalid start_sequence_number {shard_sequence_number}")
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(StartingPosition::AtSequenceNumber),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KafkaTopic {
+    pub topic: String,
+    pub bootstrap_servers: String,
+    /// The consumer group whose committed offsets reading resumes from and which commits the
+    /// offsets read. Without partitions given, the partitions of the topic are balanced across
+    /// the members of the group.
+    pub group_id: Option<String>,
+    /// The partitions read, instead of those assigned by the consumer group. All partitions
+    /// are read when neither are given.
+    pub partitions: Option<Vec<i32>>,
+    pub starting_offsets: StartingOffsets,
+    pub format: Format,
+    pub error_policy: ErrorPolicy,
+    pub watermark: Option<Watermark>,
+}
+
+/// Where reading the partitions of a Kafka topic starts. The committed offsets of the consumer
+/// group, if any, come before the earliest or latest offsets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum StartingOffsets {
+    /// The oldest message still retained.
+    #[default]
+    Earliest,
+    /// Only messages produced after the partitions are assigned.
+    Latest,
+    /// The first message with a timestamp at or after the time, in milliseconds since the
+    /// epoch.
+    AtTimestamp(i64),
+    /// The given offset, by partition. Partitions without one start at the earliest offset.
+    AtOffset(Vec<(i32, i64)>),
+}
+
+impl StartingOffsets {
+    /// Reads the `start`, `start_timestamp` or `start_offsets` option of a Kafka topic,
+    /// returning `None` for any other option.
+    pub fn from_option(key: &str, value: &str) -> Option<Result<StartingOffsets, String>> {
+        match key {
+            "start" => Some(match value.to_uppercase().as_str() {
+                "EARLIEST" => Ok(StartingOffsets::Earliest),
+                "LATEST" => Ok(StartingOffsets::Latest),
+                _ => Err(format!("start needs to be EARLIEST or LATEST, not {value}")),
+            }),
+            "start_timestamp" => Some(
+                chrono::DateTime::parse_from_rfc3339(value)
+                    .map(|timestamp| StartingOffsets::AtTimestamp(timestamp.timestamp_millis()))
+                    .map_err(|error| format!("invalid start_timestamp {value}: {error}")),
+            ),
+            // Given as `partition:offset`, separated by commas.
+            "start_offsets" => Some(
+                value
+                    .split(',')
+                    .map(|partition_offset| {
+                        partition_offset
+                            .trim()
+                            .split_once(':')
+                            .and_then(|(partition, offset)| {
+                                Some((partition.parse().ok()?, offset.parse().ok()?))
+                            })
+                            .ok_or_else(|| format!("invalid start_offsets {partition_offset}"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(StartingOffsets::AtOffset),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    pub path: String,
+    pub format: Format,
+    pub error_policy: ErrorPolicy,
+    /// Keep reading lines appended to the file instead of ending at its end.
+    pub tail: bool,
+    pub watermark: Option<Watermark>,
+}
+
+/// A query reading a socket source listens on `address` while it runs.
+#[derive(Debug, Clone)]
+pub struct SocketSource {
+    pub protocol: SocketProtocol,
+    pub address: String,
+    pub format: Format,
+    pub error_policy: ErrorPolicy,
+    pub watermark: Option<Watermark>,
+}
+
+/// A query reading an HTTP source listens on `address` while it runs, reading a payload from
+/// every line of the bodies POSTed to it.
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    pub address: String,
+    pub format: Format,
+    pub error_policy: ErrorPolicy,
+    pub watermark: Option<Watermark>,
+}
+
+/// Records generated with a value for every field, at `rate` records per second, or as fast as
+/// they are read without one, until `rows` records have been generated, if given.
+#[derive(Debug, Clone)]
+pub struct GeneratorSource {
+    pub fields: Vec<(String, FieldGenerator)>,
+    pub rate: Option<u64>,
+    pub rows: Option<u64>,
+    pub watermark: Option<Watermark>,
+}
+
+/// How the values of a field of a generator source are generated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldGenerator {
+    /// `sequence`: 1, 2, 3, ...
+    Sequence,
+    /// `int(min, max)`: a random integer between `min` and `max`, inclusive.
+    Int(i64, i64),
+    /// `string(value, ...)`: one of the values, picked at random.
+    String(Vec<String>),
+    /// `timestamp`: the time the record is generated, in milliseconds since the epoch.
+    Timestamp,
+}
+
+impl FieldGenerator {
+    /// Reads the `fields` option of a generator source, such as
+    /// `id sequence, user_id int(1, 100), country string(US, DE), ts timestamp`.
+    pub fn parse_fields(fields: &str) -> Result<Vec<(String, FieldGenerator)>, String> {
+        let mut field_specs = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+
+        // Commas within the parentheses of a generator separate its arguments.
+        for (i, ch) in fields.char_indices() {
+            match ch {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    field_specs.push(&fields[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+
+        field_specs.push(&fields[start..]);
+
+        field_specs
+            .into_iter()
+            .map(|field_spec| {
+                let (name, generator) = field_spec
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| format!("invalid field {field_spec}"))?;
+
+                Ok((name.to_string(), FieldGenerator::parse(generator.trim())?))
+            })
+            .collect()
+    }
+
+    fn parse(generator: &str) -> Result<FieldGenerator, String> {
+        let (name, args) = match generator.split_once('(') {
+            Some((name, args)) => (
+                name.trim(),
+                args.strip_suffix(')')
+                    .ok_or_else(|| format!("invalid field generator {generator}"))?
+                    .split(',')
+                    .map(str::trim)
+                    .collect(),
+            ),
+            None => (generator, Vec::new()),
+        };
+
+        match (name.to_lowercase().as_str(), args.as_slice()) {
+            ("sequence", []) => Ok(FieldGenerator::Sequence),
+            ("int", [min, max]) => {
+                let parse_bound = |bound: &str| {
+                    bound
+                        .parse::<i64>()
+                        .map_err(|_| format!("invalid bound {bound} of {generator}"))
+                };
+                let (min, max) = (parse_bound(min)?, parse_bound(max)?);
+
+                if min > max {
+                    return Err(format!("{generator} needs min to be at most max"));
+                }
+
+                Ok(FieldGenerator::Int(min, max))
+            }
+            // At least one value, none of them empty.
+            ("string", values @ [_, ..]) if values.iter().all(|value| !value.is_empty()) => Ok(
+                FieldGenerator::String(values.iter().map(|value| value.to_string()).collect()),
+            ),
+            ("timestamp", []) => Ok(FieldGenerator::Timestamp),
+            _ => Err(format!(
+                "field generator needs to be sequence, int(min, max), string(value, ...) or \
+                 timestamp, not {generator}"
+            )),
+        }
+    }
+}
+
+/// Records inserted into a file sink are written to the file at `path`, or, when the sink
+/// rotates files, to files named after it and the time each was opened.
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    pub path: String,
+    pub format: FileSinkFormat,
+    /// Start a new file once the current one has grown to this many bytes.
+    pub rotate_bytes: Option<u64>,
+    /// Start a new file once the current one has been open this long.
+    pub rotate_interval: Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,3 +360,11 @@ pub enum FunctionDefinition {
 pub type NativeFunction = fn(args: Vec<serde_json::Value>) -> serde_json::Value;
 
 pub type Record = serde_json::Map<String, serde_json::Value>;
+
+/// What flows through the channels between operators. A watermark promises that no further
+/// record will have an event time, in milliseconds since the epoch, before it.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Record(Record),
+    Watermark(i64),
+}
@@ -0,0 +1,694 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    definitions::{Catalog, Message, Record},
+    planners::{AggregateCall, AggregateFunction, Window, WINDOW_END, WINDOW_START},
+    sql::Expr,
+};
+
+use super::{evaluate_expr, event_time, format_event_time};
+
+pub async fn execute_aggregate(
+    catalog: &Catalog,
+    group_by: HashMap<String, Expr>,
+    aggregates: HashMap<String, AggregateCall>,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+) {
+    let mut groups: HashMap<String, (Record, HashMap<String, Accumulator>)> = HashMap::new();
+
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(input_record) = message else {
+            sender.send(message).await.unwrap();
+            continue;
+        };
+
+        let group_record = Record::from_iter(
+            group_by
+                .iter()
+                .map(|(key, expr)| (key.clone(), evaluate_expr(catalog, &input_record, expr))),
+        );
+        let group_key = serde_json::to_string(&group_record).unwrap();
+
+        let (group_record, accumulators) = groups
+            .entry(group_key)
+            .or_insert_with(|| (group_record, new_accumulators(&aggregates)));
+
+        update_accumulators(catalog, &aggregates, accumulators, &input_record);
+
+        // Every input record emits the updated row of its group, so downstream consumers
+        // should treat the output as upserts keyed by the group columns.
+        let mut output_record = group_record.clone();
+
+        for (key, accumulator) in accumulators.iter() {
+            output_record.insert(key.clone(), accumulator.value());
+        }
+
+        sender.send(Message::Record(output_record)).await.unwrap();
+    }
+}
+
+/// Open windows keyed by `(end, start, group key)`, so that the windows to close once event
+/// time has passed their end are always at the front.
+type Windows = BTreeMap<(i64, i64, String), (Record, HashMap<String, Accumulator>)>;
+
+pub async fn execute_window_aggregate(
+    catalog: &Catalog,
+    window: Window,
+    time: Expr,
+    group_by: HashMap<String, Expr>,
+    aggregates: HashMap<String, AggregateCall>,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+) {
+    let mut windows = Windows::new();
+    // Windows are emitted once the watermark passes their end, or once the input ends for
+    // relations without a WATERMARK clause; records that only fall into windows which have
+    // already been emitted are dropped as late.
+    let mut current_watermark = i64::MIN;
+
+    while let Some(message) = receiver.recv().await {
+        let input_record = match message {
+            Message::Record(input_record) => input_record,
+            Message::Watermark(watermark) => {
+                current_watermark = current_watermark.max(watermark);
+                close_windows(&mut windows, current_watermark, &sender).await;
+                sender.send(message).await.unwrap();
+                continue;
+            }
+        };
+
+        let Some(record_time) = event_time(&evaluate_expr(catalog, &input_record, &time)) else {
+            eprintln!(
+                "could not read event time from {}",
+                serde_json::Value::from(input_record)
+            );
+            continue;
+        };
+
+        let group_record = Record::from_iter(
+            group_by
+                .iter()
+                .map(|(key, expr)| (key.clone(), evaluate_expr(catalog, &input_record, expr))),
+        );
+        let group_key = serde_json::to_string(&group_record).unwrap();
+
+        let Some(window_bounds) = window_bounds(window, record_time) else {
+            eprintln!(
+                "the window of event time {record_time} is out of range, leaving out {}",
+                serde_json::Value::from(input_record)
+            );
+            continue;
+        };
+
+        let window_bounds = match window {
+            Window::Tumbling { .. } | Window::Hopping { .. } => window_bounds,
+            Window::Session { .. } => {
+                let [(mut start, mut end)] = window_bounds[..] else {
+                    unreachable!("a record starts a single session");
+                };
+
+                if end <= current_watermark {
+                    drop_late_record(input_record, current_watermark);
+                    continue;
+                }
+
+                // Sessions of the same group that overlap the new record are merged into it.
+                let overlapping_keys = windows
+                    .range((record_time, i64::MIN, String::new())..)
+                    .map(|(key, _)| key)
+                    .filter(|(session_end, session_start, session_group_key)| {
+                        *session_end > record_time
+                            && *session_start < end
+                            && *session_group_key == group_key
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                let mut accumulators = new_accumulators(&aggregates);
+
+                for overlapping_key in overlapping_keys {
+                    let (_, session_accumulators) = windows.remove(&overlapping_key).unwrap();
+
+                    for (key, accumulator) in session_accumulators {
+                        accumulators.get_mut(&key).unwrap().merge(accumulator);
+                    }
+
+                    end = end.max(overlapping_key.0);
+                    start = start.min(overlapping_key.1);
+                }
+
+                windows.insert(
+                    (end, start, group_key.clone()),
+                    (group_record.clone(), accumulators),
+                );
+
+                vec![(start, end)]
+            }
+        };
+
+        // Hopping windows shorter than their slide leave gaps that hold no window at all.
+        if !window_bounds.is_empty()
+            && window_bounds
+                .iter()
+                .all(|(_, end)| *end <= current_watermark)
+        {
+            drop_late_record(input_record, current_watermark);
+            continue;
+        }
+
+        for (start, end) in window_bounds {
+            if end <= current_watermark {
+                continue;
+            }
+
+            let (_, accumulators) = windows
+                .entry((end, start, group_key.clone()))
+                .or_insert_with(|| (group_record.clone(), new_accumulators(&aggregates)));
+
+            update_accumulators(catalog, &aggregates, accumulators, &input_record);
+        }
+    }
+
+    close_windows(&mut windows, i64::MAX, &sender).await;
+}
+
+/// The `(start, end)` of the windows an event time falls into, or of the session it starts,
+/// or `None` if one of them starts or ends outside the representable event times.
+fn window_bounds(window: Window, time: i64) -> Option<Vec<(i64, i64)>> {
+    match window {
+        Window::Tumbling { size } => {
+            let size = size as i64;
+            let start = time.checked_sub(time.rem_euclid(size))?;
+            Some(vec![(start, start.checked_add(size)?)])
+        }
+        Window::Hopping { slide, size } => {
+            let (slide, size) = (slide as i64, size as i64);
+            let mut start = time.checked_sub(time.rem_euclid(slide))?;
+            let mut window_bounds = Vec::new();
+
+            loop {
+                let end = start.checked_add(size)?;
+
+                if end <= time {
+                    break;
+                }
+
+                window_bounds.push((start, end));
+
+                // Earlier windows starting before the first representable event time are left
+                // out.
+                let Some(previous_start) = start.checked_sub(slide) else {
+                    break;
+                };
+
+                start = previous_start;
+            }
+
+            Some(window_bounds)
+        }
+        Window::Session { gap } => Some(vec![(time, time.checked_add(gap as i64)?)]),
+    }
+}
+
+fn drop_late_record(record: Record, watermark: i64) {
+    eprintln!(
+        "dropped {}, whose windows were closed by the watermark {}",
+        serde_json::Value::from(record),
+        format_event_time(watermark)
+    );
+}
+
+/// Emits and forgets every window that ends at or before `time`, in order of window end.
+async fn close_windows(windows: &mut Windows, time: i64, sender: &mpsc::Sender<Message>) {
+    while let Some(entry) = windows.first_entry() {
+        let (end, start, _) = *entry.key();
+
+        if end > time {
+            break;
+        }
+
+        let (group_record, accumulators) = entry.remove();
+
+        let mut output_record = group_record;
+        output_record.insert(WINDOW_START.to_string(), format_event_time(start));
+        output_record.insert(WINDOW_END.to_string(), format_event_time(end));
+
+        for (key, accumulator) in accumulators.iter() {
+            output_record.insert(key.clone(), accumulator.value());
+        }
+
+        sender.send(Message::Record(output_record)).await.unwrap();
+    }
+}
+
+fn new_accumulators(aggregates: &HashMap<String, AggregateCall>) -> HashMap<String, Accumulator> {
+    aggregates
+        .iter()
+        .map(|(key, aggregate_call)| (key.clone(), Accumulator::new(aggregate_call.function)))
+        .collect()
+}
+
+fn update_accumulators(
+    catalog: &Catalog,
+    aggregates: &HashMap<String, AggregateCall>,
+    accumulators: &mut HashMap<String, Accumulator>,
+    record: &Record,
+) {
+    for (key, aggregate_call) in aggregates.iter() {
+        let value = aggregate_call
+            .args
+            .first()
+            .map(|expr| evaluate_expr(catalog, record, expr));
+
+        accumulators.get_mut(key).unwrap().update(value);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+    Count(u64),
+    Sum(Option<f64>),
+    Min(Option<serde_json::Value>),
+    Max(Option<serde_json::Value>),
+    Avg { sum: f64, count: u64 },
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction) -> Accumulator {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum(None),
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+            AggregateFunction::Avg => Accumulator::Avg { sum: 0.0, count: 0 },
+        }
+    }
+
+    /// Folds a value into the accumulator. `None` means the aggregate was called without an
+    /// argument, as in `count(*)`. NULL values are ignored by every aggregate.
+    pub fn update(&mut self, value: Option<serde_json::Value>) {
+        if let Some(serde_json::Value::Null) = value {
+            return;
+        }
+
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => {
+                if let Some(number) = value.as_ref().and_then(serde_json::Value::as_f64) {
+                    *sum = Some(sum.unwrap_or(0.0) + number);
+                }
+            }
+            Accumulator::Min(min) => {
+                if let Some(value) = value {
+                    if min
+                        .as_ref()
+                        .is_none_or(|min| compare_values(&value, min) == Some(Ordering::Less))
+                    {
+                        *min = Some(value);
+                    }
+                }
+            }
+            Accumulator::Max(max) => {
+                if let Some(value) = value {
+                    if max
+                        .as_ref()
+                        .is_none_or(|max| compare_values(&value, max) == Some(Ordering::Greater))
+                    {
+                        *max = Some(value);
+                    }
+                }
+            }
+            Accumulator::Avg { sum, count } => {
+                if let Some(number) = value.as_ref().and_then(serde_json::Value::as_f64) {
+                    *sum += number;
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    /// Combines the state of two accumulators of the same aggregate function, used when
+    /// session windows are merged.
+    pub fn merge(&mut self, other: Accumulator) {
+        match (self, other) {
+            (Accumulator::Count(count), Accumulator::Count(other_count)) => *count += other_count,
+            (Accumulator::Sum(sum), Accumulator::Sum(other_sum)) => {
+                if let Some(other_sum) = other_sum {
+                    *sum = Some(sum.unwrap_or(0.0) + other_sum);
+                }
+            }
+            (min @ Accumulator::Min(_), Accumulator::Min(other_min)) => min.update(other_min),
+            (max @ Accumulator::Max(_), Accumulator::Max(other_max)) => max.update(other_max),
+            (
+                Accumulator::Avg { sum, count },
+                Accumulator::Avg {
+                    sum: other_sum,
+                    count: other_count,
+                },
+            ) => {
+                *sum += other_sum;
+                *count += other_count;
+            }
+            _ => panic!("cannot merge accumulators of different aggregate functions"),
+        }
+    }
+
+    pub fn value(&self) -> serde_json::Value {
+        match self {
+            Accumulator::Count(count) => serde_json::Value::from(*count),
+            Accumulator::Sum(sum) => sum.map_or(serde_json::Value::Null, serde_json::Value::from),
+            Accumulator::Min(value) | Accumulator::Max(value) => {
+                value.clone().unwrap_or(serde_json::Value::Null)
+            }
+            Accumulator::Avg { sum, count } => {
+                if *count == 0 {
+                    serde_json::Value::Null
+                } else {
+                    serde_json::Value::from(sum / *count as f64)
+                }
+            }
+        }
+    }
+}
+
+fn compare_values(left: &serde_json::Value, right: &serde_json::Value) -> Option<Ordering> {
+    match (left, right) {
+        (serde_json::Value::Number(left), serde_json::Value::Number(right)) => {
+            left.as_f64().unwrap().partial_cmp(&right.as_f64().unwrap())
+        }
+        (serde_json::Value::String(left), serde_json::Value::String(right)) => {
+            Some(left.cmp(right))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::definitions::OutputFormat;
+
+    fn new_catalog() -> Catalog {
+        Catalog {
+            relations: HashMap::new(),
+            functions: HashMap::new(),
+            output_format: OutputFormat::default(),
+        }
+    }
+
+    fn accumulate(function: AggregateFunction, values: &[serde_json::Value]) -> serde_json::Value {
+        let mut accumulator = Accumulator::new(function);
+
+        for value in values {
+            accumulator.update(Some(value.clone()));
+        }
+
+        accumulator.value()
+    }
+
+    fn click(user: serde_json::Value, ts: i64) -> Message {
+        Message::Record(Record::from_iter([
+            ("user".to_string(), user),
+            ("ts".to_string(), serde_json::Value::from(ts)),
+        ]))
+    }
+
+    /// Runs an aggregate node over the messages, returning the records it emits.
+    async fn aggregate(
+        window: Option<Window>,
+        group_by: &[&str],
+        messages: Vec<Message>,
+    ) -> Vec<Record> {
+        let catalog = new_catalog();
+        let group_by = group_by
+            .iter()
+            .map(|key| (key.to_string(), Expr::Ident(key.to_string())))
+            .collect();
+        let aggregates = HashMap::from([(
+            "clicks".to_string(),
+            AggregateCall {
+                function: AggregateFunction::Count,
+                args: vec![],
+            },
+        )]);
+
+        let (input_sender, input_receiver) = mpsc::channel(messages.len() + 1);
+        let (output_sender, mut output_receiver) = mpsc::channel(messages.len() * 4 + 1);
+
+        for message in messages {
+            input_sender.send(message).await.unwrap();
+        }
+
+        drop(input_sender);
+
+        match window {
+            Some(window) => {
+                execute_window_aggregate(
+                    &catalog,
+                    window,
+                    Expr::Ident("ts".to_string()),
+                    group_by,
+                    aggregates,
+                    input_receiver,
+                    output_sender,
+                )
+                .await
+            }
+            None => {
+                execute_aggregate(
+                    &catalog,
+                    group_by,
+                    aggregates,
+                    input_receiver,
+                    output_sender,
+                )
+                .await
+            }
+        }
+
+        let mut records = Vec::new();
+
+        while let Some(message) = output_receiver.recv().await {
+            if let Message::Record(record) = message {
+                records.push(record);
+            }
+        }
+
+        records
+    }
+
+    #[test]
+    fn ignores_nulls() {
+        let values = [
+            serde_json::Value::from(3),
+            serde_json::Value::Null,
+            serde_json::Value::from(1),
+        ];
+
+        assert_eq!(accumulate(AggregateFunction::Count, &values), 2);
+        assert_eq!(accumulate(AggregateFunction::Sum, &values), 4.0);
+        assert_eq!(accumulate(AggregateFunction::Min, &values), 1);
+        assert_eq!(accumulate(AggregateFunction::Max, &values), 3);
+        assert_eq!(accumulate(AggregateFunction::Avg, &values), 2.0);
+    }
+
+    #[test]
+    fn aggregates_nothing_but_nulls_to_null() {
+        let values = [serde_json::Value::Null];
+
+        assert_eq!(accumulate(AggregateFunction::Count, &values), 0);
+        assert_eq!(
+            accumulate(AggregateFunction::Sum, &values),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            accumulate(AggregateFunction::Min, &values),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            accumulate(AggregateFunction::Max, &values),
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            accumulate(AggregateFunction::Avg, &values),
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn counts_every_row_without_an_argument() {
+        let mut accumulator = Accumulator::new(AggregateFunction::Count);
+
+        accumulator.update(None);
+        accumulator.update(None);
+
+        assert_eq!(accumulator.value(), 2);
+    }
+
+    #[test]
+    fn merges_accumulators() {
+        let mut sum = Accumulator::Sum(None);
+        sum.merge(Accumulator::Sum(Some(2.0)));
+        sum.merge(Accumulator::Sum(None));
+        assert_eq!(sum.value(), 2.0);
+
+        let mut min = Accumulator::Min(Some(serde_json::Value::from("b")));
+        min.merge(Accumulator::Min(None));
+        min.merge(Accumulator::Min(Some(serde_json::Value::from("a"))));
+        assert_eq!(min.value(), "a");
+
+        let mut avg = Accumulator::Avg { sum: 1.0, count: 1 };
+        avg.merge(Accumulator::Avg { sum: 5.0, count: 2 });
+        assert_eq!(avg.value(), 2.0);
+    }
+
+    #[test]
+    fn computes_window_bounds() {
+        assert_eq!(
+            window_bounds(Window::Tumbling { size: 10 }, 25),
+            Some(vec![(20, 30)])
+        );
+        // Event times before the epoch fall into the windows below them.
+        assert_eq!(
+            window_bounds(Window::Tumbling { size: 10 }, -1),
+            Some(vec![(-10, 0)])
+        );
+        assert_eq!(
+            window_bounds(Window::Hopping { slide: 5, size: 10 }, 7),
+            Some(vec![(5, 15), (0, 10)])
+        );
+        assert_eq!(
+            window_bounds(Window::Hopping { slide: 10, size: 5 }, 7),
+            Some(vec![])
+        );
+        assert_eq!(
+            window_bounds(Window::Session { gap: 10 }, 7),
+            Some(vec![(7, 17)])
+        );
+    }
+
+    #[test]
+    fn leaves_out_windows_that_cannot_be_represented() {
+        assert_eq!(window_bounds(Window::Tumbling { size: 10 }, i64::MAX), None);
+        assert_eq!(window_bounds(Window::Session { gap: 10 }, i64::MAX), None);
+        // Hopping windows starting before the first representable event time are left out.
+        assert_eq!(
+            window_bounds(Window::Hopping { slide: 2, size: 4 }, i64::MIN + 2),
+            Some(vec![(i64::MIN + 2, i64::MIN + 6), (i64::MIN, i64::MIN + 4)])
+        );
+    }
+
+    #[tokio::test]
+    async fn emits_the_updated_group_of_every_record() {
+        let records = aggregate(
+            None,
+            &["user"],
+            vec![
+                click(serde_json::Value::from("alice"), 0),
+                click(serde_json::Value::Null, 0),
+                click(serde_json::Value::from("alice"), 0),
+                click(serde_json::Value::Null, 0),
+            ],
+        )
+        .await;
+
+        let counts = records
+            .iter()
+            .map(|record| (record["user"].clone(), record["clicks"].clone()))
+            .collect::<Vec<_>>();
+
+        // NULL keys form a group of their own.
+        assert_eq!(
+            counts,
+            [
+                (serde_json::Value::from("alice"), serde_json::Value::from(1)),
+                (serde_json::Value::Null, serde_json::Value::from(1)),
+                (serde_json::Value::from("alice"), serde_json::Value::from(2)),
+                (serde_json::Value::Null, serde_json::Value::from(2)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closes_windows_once_the_watermark_passes_them() {
+        let alice = || serde_json::Value::from("alice");
+
+        let records = aggregate(
+            Some(Window::Tumbling { size: 10 }),
+            &["user"],
+            vec![
+                click(alice(), 1),
+                click(alice(), 12),
+                // Out of order, but its window is still open.
+                click(alice(), 3),
+                Message::Watermark(10),
+                // Late, as the watermark closed its window.
+                click(alice(), 5),
+                click(alice(), 15),
+            ],
+        )
+        .await;
+
+        let windows = records
+            .iter()
+            .map(|record| {
+                (
+                    record[WINDOW_START].clone(),
+                    record[WINDOW_END].clone(),
+                    record["clicks"].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            windows,
+            [
+                (
+                    format_event_time(0),
+                    format_event_time(10),
+                    serde_json::Value::from(2)
+                ),
+                (
+                    format_event_time(10),
+                    format_event_time(20),
+                    serde_json::Value::from(2)
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merges_overlapping_sessions() {
+        let records = aggregate(
+            Some(Window::Session { gap: 10 }),
+            &[],
+            vec![
+                click(serde_json::Value::Null, 0),
+                click(serde_json::Value::Null, 18),
+                // Bridges the two sessions above.
+                click(serde_json::Value::Null, 9),
+                click(serde_json::Value::Null, 100),
+            ],
+        )
+        .await;
+
+        let sessions = records
+            .iter()
+            .map(|record| (record[WINDOW_END].clone(), record["clicks"].clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sessions,
+            [
+                (format_event_time(28), serde_json::Value::from(3)),
+                (format_event_time(110), serde_json::Value::from(1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use base64::Engine;
+use futures_util::FutureExt;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::mpsc};
+
+use crate::{
+    definitions::{Catalog, Message, Record, RelationDefinition},
+    sql::ErrorPolicy,
+};
+
+use super::{decoders::Decoder, kafka, kinesis, PlanFuture};
+
+/// Columns of a dead letter, next to the metadata columns naming where its payload was read
+/// from. Those lose their leading underscore, so that they are not overwritten by the metadata
+/// columns of a Kinesis stream the dead letters are read back from. The payload is
+/// base64-encoded.
+pub const ERROR: &str = "error";
+pub const PAYLOAD: &str = "payload";
+
+/// The error policy of a relation being read. Dead letters are sent to the future writing them
+/// to the sink of the policy.
+#[derive(Debug, Clone)]
+pub enum ErrorHandler {
+    Skip,
+    Fail,
+    DeadLetter(mpsc::Sender<Message>),
+}
+
+/// Decodes the payloads of a relation, applying its error policy to those that cannot be
+/// decoded.
+pub struct PayloadDecoder {
+    decoder: Box<dyn Decoder>,
+    error_handler: ErrorHandler,
+}
+
+impl PayloadDecoder {
+    pub fn new(decoder: Box<dyn Decoder>, error_handler: ErrorHandler) -> PayloadDecoder {
+        PayloadDecoder {
+            decoder,
+            error_handler,
+        }
+    }
+
+    /// Returns the records decoded from the payload, or none if it cannot be decoded, unless
+    /// the error policy is to fail. `metadata` names where the payload was read from.
+    pub async fn decode(
+        &mut self,
+        payload: &[u8],
+        metadata: &Record,
+    ) -> Result<Vec<Record>, String> {
+        let error = match self.decoder.decode(payload) {
+            Ok(records) => return Ok(records),
+            Err(error) => error,
+        };
+
+        let source = serde_json::Value::Object(metadata.clone());
+
+        match &self.error_handler {
+            ErrorHandler::Skip => eprintln!("could not decode payload {source}: {error}"),
+            ErrorHandler::Fail => {
+                return Err(format!("could not decode payload {source}: {error}"))
+            }
+            ErrorHandler::DeadLetter(dead_letter_sender) => {
+                let mut dead_letter =
+                    Record::from_iter(metadata.iter().map(|(key, value)| {
+                        (key.trim_start_matches('_').to_string(), value.clone())
+                    }));
+
+                dead_letter.insert(ERROR.to_string(), serde_json::Value::from(error));
+                dead_letter.insert(
+                    PAYLOAD.to_string(),
+                    serde_json::Value::from(
+                        base64::engine::general_purpose::STANDARD.encode(payload),
+                    ),
+                );
+
+                dead_letter_sender
+                    .send(Message::Record(dead_letter))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+/// Returns the error handler of the policy, adding the future writing its dead letters, if
+/// any, to `futures`.
+pub fn new_error_handler<'a>(
+    catalog: &'a Catalog,
+    error_policy: ErrorPolicy,
+    futures: &mut Vec<PlanFuture<'a>>,
+) -> ErrorHandler {
+    match error_policy {
+        ErrorPolicy::Skip => ErrorHandler::Skip,
+        ErrorPolicy::Fail => ErrorHandler::Fail,
+        ErrorPolicy::DeadLetterFile(_) | ErrorPolicy::DeadLetterRelation(_) => {
+            let (dead_letter_sender, dead_letter_receiver) = mpsc::channel(256);
+
+            futures.push(Box::pin(
+                execute_write_dead_letters(catalog, error_policy, dead_letter_receiver).map(Ok),
+            ));
+
+            ErrorHandler::DeadLetter(dead_letter_sender)
+        }
+    }
+}
+
+/// Returns an error unless the relation dead letters are put into is a Kinesis stream or Kafka
+/// topic.
+pub fn check_error_policy(catalog: &Catalog, error_policy: &ErrorPolicy) -> Result<(), String> {
+    if let ErrorPolicy::DeadLetterRelation(relation_ident) = error_policy {
+        if !matches!(
+            catalog.relations.get(relation_ident),
+            Some(RelationDefinition::KinesisStream(_) | RelationDefinition::KafkaTopic(_))
+        ) {
+            return Err(format!(
+                "{relation_ident} is not a Kinesis stream or Kafka topic"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the dead letters to a file as JSON lines, or writes them to a relation the way
+/// INSERT does, spread over its shards or partitions.
+async fn execute_write_dead_letters(
+    catalog: &Catalog,
+    error_policy: ErrorPolicy,
+    mut receiver: mpsc::Receiver<Message>,
+) {
+    match error_policy {
+        ErrorPolicy::DeadLetterFile(path) => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await
+                .unwrap_or_else(|error| panic!("could not open {path}: {error}"));
+
+            while let Some(message) = receiver.recv().await {
+                let Message::Record(dead_letter) = message else {
+                    continue;
+                };
+
+                let mut line = serde_json::to_vec(&dead_letter).unwrap();
+                line.push(b'\n');
+
+                file.write_all(&line).await.unwrap();
+            }
+        }
+        ErrorPolicy::DeadLetterRelation(relation_ident) => {
+            match catalog.relations.get(&relation_ident) {
+                Some(RelationDefinition::KinesisStream(kinesis_stream)) => {
+                    kinesis::execute_write_kinesis_stream(catalog, kinesis_stream, None, receiver)
+                        .await
+                }
+                Some(RelationDefinition::KafkaTopic(kafka_topic)) => {
+                    kafka::execute_write_kafka_topic(catalog, kafka_topic, None, receiver).await
+                }
+                _ => panic!("{relation_ident} is not a Kinesis stream or Kafka topic"),
+            }
+        }
+        ErrorPolicy::Skip | ErrorPolicy::Fail => {}
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::Read;
+
+use apache_avro::{reader::datum::GenericDatumReader, Schema};
+use base64::Engine;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor, SerializeOptions};
+
+use crate::{
+    definitions::Record,
+    sql::{Compression, Encoding, Format},
+};
+
+/// The column holding the whole payload of a relation with the TEXT or BYTES format.
+pub const DATA: &str = "data";
+
+/// Decodes the payloads of a relation, the lines of a file or the data of the records of a
+/// Kinesis shard, into records. One decoder is used for the payloads of a file or shard in
+/// order, so it may keep state between them, such as the header of a CSV file.
+pub trait Decoder: Send {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String>;
+}
+
+/// Returns a decoder of the format, reading any schema or descriptor file it names.
+pub fn new_decoder(format: &Format) -> Result<Box<dyn Decoder>, String> {
+    let decoder: Box<dyn Decoder> = match &format.encoding {
+        Encoding::Json => Box::new(JsonDecoder),
+        Encoding::Csv(columns) => Box::new(CsvDecoder {
+            columns: columns.clone(),
+        }),
+        Encoding::Avro(schema_path) => Box::new(AvroDecoder::new(schema_path)?),
+        Encoding::Protobuf(message_name, descriptor_set_path) => {
+            Box::new(ProtobufDecoder::new(message_name, descriptor_set_path)?)
+        }
+        Encoding::Text => Box::new(TextDecoder),
+        Encoding::Bytes => Box::new(BytesDecoder),
+    };
+
+    Ok(match format.compression {
+        Some(compression) => Box::new(DecompressingDecoder {
+            compression,
+            decoder,
+        }),
+        None => decoder,
+    })
+}
+
+/// Checks that the format can decode the lines of a relation read line by line, such as a
+/// file. Compressed, Avro, protobuf and arbitrary binary payloads may contain newlines
+/// themselves, so they are only read from relations whose payloads are delimited otherwise.
+pub fn check_line_format(format: &Format) -> Result<(), String> {
+    if format.compression.is_some() {
+        return Err("COMPRESSION needs payloads that are not split into lines".to_string());
+    }
+
+    match format.encoding {
+        Encoding::Json | Encoding::Csv(_) | Encoding::Text => Ok(()),
+        Encoding::Avro(_) | Encoding::Protobuf(_, _) | Encoding::Bytes => {
+            Err("a relation read line by line needs to be FORMAT JSON, CSV or TEXT".to_string())
+        }
+    }
+}
+
+/// Checks that the format can decode payloads which are not all read by one decoder in order,
+/// such as the records of a Kinesis stream, which are spread over its shards. A CSV header
+/// would only be the first payload read by one of the decoders, so the columns need to be
+/// given instead.
+pub fn check_record_format(format: &Format) -> Result<(), String> {
+    match format.encoding {
+        Encoding::Csv(None) => Err(
+            "FORMAT CSV needs COLUMNS (column, ...) for a relation whose records are not read \
+            in order"
+                .to_string(),
+        ),
+        _ => Ok(()),
+    }
+}
+
+struct JsonDecoder;
+
+impl Decoder for JsonDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let record =
+            serde_json::from_slice::<Record>(payload).map_err(|error| error.to_string())?;
+
+        Ok(vec![record])
+    }
+}
+
+/// Decodes every line of a payload as a record. Without columns given, the first line read is
+/// the header naming them.
+struct CsvDecoder {
+    columns: Option<Vec<String>>,
+}
+
+impl Decoder for CsvDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(payload);
+
+        let mut records = Vec::new();
+
+        for fields in reader.records() {
+            let fields = fields.map_err(|error| error.to_string())?;
+
+            match &self.columns {
+                Some(columns) => records.push(Record::from_iter(
+                    columns
+                        .iter()
+                        .cloned()
+                        .zip(fields.iter().map(parse_csv_field)),
+                )),
+                None => self.columns = Some(fields.iter().map(str::to_string).collect()),
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+/// Fields that are valid JSON numbers are read as numbers, every other field as a string.
+fn parse_csv_field(field: &str) -> serde_json::Value {
+    serde_json::from_str::<serde_json::Number>(field).map_or_else(
+        |_| serde_json::Value::from(field),
+        serde_json::Value::Number,
+    )
+}
+
+/// Decodes a payload as a single Avro datum, without the header of an object container file.
+struct AvroDecoder {
+    schema: Schema,
+}
+
+impl AvroDecoder {
+    fn new(schema_path: &str) -> Result<AvroDecoder, String> {
+        let schema = std::fs::read_to_string(schema_path)
+            .map_err(|error| format!("could not read {schema_path}: {error}"))?;
+
+        Ok(AvroDecoder {
+            schema: Schema::parse_str(&schema)
+                .map_err(|error| format!("invalid Avro schema {schema_path}: {error}"))?,
+        })
+    }
+}
+
+impl Decoder for AvroDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let value = GenericDatumReader::builder(&self.schema)
+            .build()
+            .and_then(|reader| reader.read_value(&mut &*payload))
+            .and_then(serde_json::Value::try_from)
+            .map_err(|error| error.to_string())?;
+
+        match value {
+            serde_json::Value::Object(record) => Ok(vec![record]),
+            _ => Err("the Avro schema is not a record".to_string()),
+        }
+    }
+}
+
+/// Decodes a payload as a protobuf message, with the fields as columns under the names in the
+/// .proto file.
+struct ProtobufDecoder {
+    message_descriptor: MessageDescriptor,
+}
+
+impl ProtobufDecoder {
+    fn new(message_name: &str, descriptor_set_path: &str) -> Result<ProtobufDecoder, String> {
+        let descriptor_set = std::fs::read(descriptor_set_path)
+            .map_err(|error| format!("could not read {descriptor_set_path}: {error}"))?;
+
+        let descriptor_pool =
+            DescriptorPool::decode(descriptor_set.as_slice()).map_err(|error| {
+                format!("invalid file descriptor set {descriptor_set_path}: {error}")
+            })?;
+
+        Ok(ProtobufDecoder {
+            message_descriptor: descriptor_pool
+                .get_message_by_name(message_name)
+                .ok_or_else(|| format!("no message {message_name} in {descriptor_set_path}"))?,
+        })
+    }
+}
+
+impl Decoder for ProtobufDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let message = DynamicMessage::decode(self.message_descriptor.clone(), payload)
+            .map_err(|error| error.to_string())?;
+
+        // Fields missing from the payload still get their default value.
+        let serialize_options = SerializeOptions::new()
+            .stringify_64_bit_integers(false)
+            .use_proto_field_name(true)
+            .skip_default_fields(false);
+
+        match message
+            .serialize_with_options(serde_json::value::Serializer, &serialize_options)
+            .map_err(|error| error.to_string())?
+        {
+            serde_json::Value::Object(record) => Ok(vec![record]),
+            _ => Err(format!(
+                "{} is not serialized as an object",
+                self.message_descriptor.full_name()
+            )),
+        }
+    }
+}
+
+struct TextDecoder;
+
+impl Decoder for TextDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let text = std::str::from_utf8(payload).map_err(|error| error.to_string())?;
+
+        Ok(vec![Record::from_iter([(
+            DATA.to_string(),
+            serde_json::Value::from(text),
+        )])])
+    }
+}
+
+struct BytesDecoder;
+
+impl Decoder for BytesDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        Ok(vec![Record::from_iter([(
+            DATA.to_string(),
+            serde_json::Value::from(base64::engine::general_purpose::STANDARD.encode(payload)),
+        )])])
+    }
+}
+
+struct DecompressingDecoder {
+    compression: Compression,
+    decoder: Box<dyn Decoder>,
+}
+
+impl Decoder for DecompressingDecoder {
+    fn decode(&mut self, payload: &[u8]) -> Result<Vec<Record>, String> {
+        let mut decompressed = Vec::new();
+
+        match self.compression {
+            Compression::Gzip => flate2::read::GzDecoder::new(payload)
+                .read_to_end(&mut decompressed)
+                .map(|_| ()),
+            Compression::Zstd => zstd::stream::copy_decode(payload, &mut decompressed),
+        }
+        .map_err(|error| format!("could not decompress: {error}"))?;
+
+        self.decoder.decode(&decompressed)
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+};
+
+use crate::{
+    definitions::{self, Catalog, Message, Record},
+    sql::{ErrorPolicy, Format, Watermark},
+};
+
+use super::{
+    dead_letters::{check_error_policy, ErrorHandler, PayloadDecoder},
+    decoders::{check_line_format, new_decoder},
+};
+
+/// Columns naming where the payload of a dead letter of a file source was read from.
+pub const PATH: &str = "_path";
+pub const LINE_NUMBER: &str = "_line_number";
+
+/// How long to wait before looking for lines appended to a tailed file.
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub async fn execute_read_file(
+    path: String,
+    format: Format,
+    error_handler: ErrorHandler,
+    tail: bool,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let file = File::open(&path)
+        .await
+        .map_err(|error| format!("could not open {path}: {error}"))?;
+
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let mut decoder = PayloadDecoder::new(
+        new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+        error_handler,
+    );
+
+    loop {
+        reader.read_until(b'\n', &mut line).await.unwrap();
+
+        // A line without a trailing newline may still be being written, so a tailed file is
+        // read again later; otherwise it is the last line of the file.
+        if !line.ends_with(b"\n") {
+            if tail {
+                tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+                continue;
+            }
+
+            if line.is_empty() {
+                break;
+            }
+        }
+
+        line_number += 1;
+
+        let content = line.strip_suffix(b"\n").map_or(line.as_slice(), |content| {
+            content.strip_suffix(b"\r").unwrap_or(content)
+        });
+
+        if !content.trim_ascii().is_empty() {
+            let metadata = Record::from_iter([
+                (PATH.to_string(), serde_json::Value::from(path.as_str())),
+                (
+                    LINE_NUMBER.to_string(),
+                    serde_json::Value::from(line_number),
+                ),
+            ]);
+
+            for record in decoder.decode(content, &metadata).await? {
+                sender.send(Message::Record(record)).await.unwrap();
+            }
+        }
+
+        line.clear();
+    }
+
+    Ok(())
+}
+
+pub fn execute_create_file_source(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    path: String,
+    format: Format,
+    error_policy: ErrorPolicy,
+    tail: bool,
+    watermark: Option<Watermark>,
+) {
+    if let Err(error) = check_line_format(&format).and_then(|_| new_decoder(&format)) {
+        panic!("{error}");
+    }
+
+    check_error_policy(catalog, &error_policy).unwrap_or_else(|error| panic!("{error}"));
+
+    let file_source = definitions::FileSource {
+        path,
+        format,
+        error_policy,
+        tail,
+        watermark,
+    };
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::FileSource(file_source),
+    );
+}
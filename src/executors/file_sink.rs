@@ -0,0 +1,421 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use parquet::arrow::ArrowWriter;
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    definitions::{self, Catalog, FileSink, Message, Record},
+    sql::{FileSinkFormat, Options},
+};
+
+use super::{format_field, run_blocking};
+
+/// How many records are buffered before they are written to a Parquet file, which also makes
+/// up the sample its schema is inferred from.
+const PARQUET_BATCH_RECORDS: usize = 1024;
+
+/// How many of the records waiting in the channel are written at a time.
+const WRITE_BATCH_RECORDS: usize = 1024;
+
+/// Writes the records to the files of the sink. A file is opened at the first record after the
+/// previous one was rotated, so an idle sink leaves no empty files behind. Files are written on
+/// the blocking thread pool, a batch of the records waiting in the channel at a time.
+pub async fn execute_write_file(file_sink: &FileSink, mut receiver: mpsc::Receiver<Message>) {
+    let mut sink_file: Option<SinkFile> = None;
+
+    loop {
+        let rotate_at = sink_file
+            .as_ref()
+            .zip(file_sink.rotate_interval)
+            .map(|(sink_file, rotate_interval)| sink_file.opened_at + rotate_interval);
+
+        let rotate = tokio::time::sleep_until(rotate_at.unwrap_or_else(Instant::now));
+
+        let message = tokio::select! {
+            message = receiver.recv() => message,
+            _ = rotate, if rotate_at.is_some() => {
+                let rotated_file = sink_file.take().unwrap();
+                run_blocking(move || rotated_file.close()).await;
+                continue;
+            }
+        };
+
+        let mut records = match message {
+            Some(Message::Record(record)) => vec![record],
+            Some(Message::Watermark(_)) => continue,
+            None => break,
+        };
+
+        while records.len() < WRITE_BATCH_RECORDS {
+            match receiver.try_recv() {
+                Ok(Message::Record(record)) => records.push(record),
+                Ok(Message::Watermark(_)) => {}
+                Err(_) => break,
+            }
+        }
+
+        let file_sink = file_sink.clone();
+
+        sink_file = run_blocking(move || write_records(&file_sink, sink_file, records)).await;
+    }
+
+    if let Some(sink_file) = sink_file {
+        run_blocking(move || sink_file.close()).await;
+    }
+}
+
+/// Writes the records to the current file of the sink, opening a file first when there is
+/// none and closing it once it has grown to `rotate_bytes`. Returns the file left open.
+fn write_records(
+    file_sink: &FileSink,
+    mut sink_file: Option<SinkFile>,
+    records: Vec<Record>,
+) -> Option<SinkFile> {
+    for record in records {
+        let current_file = sink_file.get_or_insert_with(|| SinkFile::open(file_sink));
+
+        current_file.write(record);
+
+        if file_sink
+            .rotate_bytes
+            .is_some_and(|rotate_bytes| current_file.bytes_written() >= rotate_bytes)
+        {
+            sink_file.take().unwrap().close();
+        }
+    }
+
+    sink_file
+}
+
+/// A file of a file sink being written.
+struct SinkFile {
+    path: String,
+    opened_at: Instant,
+    writer: SinkWriter,
+}
+
+enum SinkWriter {
+    Json {
+        writer: BufWriter<File>,
+        bytes_written: u64,
+    },
+    /// The columns are written as the header line of an empty file.
+    Csv {
+        writer: BufWriter<File>,
+        bytes_written: u64,
+        columns: Option<Vec<String>>,
+    },
+    /// The file is only written once the schema has been inferred from the first records.
+    Parquet {
+        file: Option<File>,
+        writer: Option<Box<(ArrowWriter<File>, arrow_json::ReaderBuilder)>>,
+        records: Vec<Record>,
+    },
+}
+
+impl SinkFile {
+    /// Opens the file at the path of the sink, or, when the sink rotates files, a new file
+    /// named after the path and the current time. Records are appended to JSON and CSV files.
+    fn open(file_sink: &FileSink) -> SinkFile {
+        let path = if file_sink.rotate_bytes.is_some() || file_sink.rotate_interval.is_some() {
+            rotated_path(&file_sink.path)
+        } else {
+            file_sink.path.clone()
+        };
+
+        let file = match file_sink.format {
+            FileSinkFormat::Parquet => File::create(&path),
+            _ => OpenOptions::new().create(true).append(true).open(&path),
+        }
+        .unwrap_or_else(|error| panic!("could not open {path}: {error}"));
+
+        let bytes_written = file.metadata().map_or(0, |metadata| metadata.len());
+
+        let writer = match &file_sink.format {
+            FileSinkFormat::Json => SinkWriter::Json {
+                writer: BufWriter::new(file),
+                bytes_written,
+            },
+            FileSinkFormat::Csv(columns) => SinkWriter::Csv {
+                writer: BufWriter::new(file),
+                bytes_written,
+                // A file that already has a header line is written with the columns it names.
+                columns: match bytes_written {
+                    0 => columns.clone(),
+                    _ => Some(read_csv_header(&path)),
+                },
+            },
+            FileSinkFormat::Parquet => SinkWriter::Parquet {
+                file: Some(file),
+                writer: None,
+                records: Vec::new(),
+            },
+        };
+
+        SinkFile {
+            path,
+            opened_at: Instant::now(),
+            writer,
+        }
+    }
+
+    fn write(&mut self, record: Record) {
+        let path = &self.path;
+
+        let batch_full = match &mut self.writer {
+            SinkWriter::Json {
+                writer,
+                bytes_written,
+            } => {
+                let mut line = serde_json::to_vec(&record).unwrap();
+                line.push(b'\n');
+
+                writer
+                    .write_all(&line)
+                    .unwrap_or_else(|error| panic!("could not write to {path}: {error}"));
+                *bytes_written += line.len() as u64;
+                false
+            }
+            SinkWriter::Csv {
+                writer,
+                bytes_written,
+                columns,
+            } => {
+                let mut lines = Vec::new();
+
+                let columns = columns.get_or_insert_with(|| record.keys().cloned().collect());
+
+                if *bytes_written == 0 {
+                    lines.extend(csv_line(columns.iter()));
+                }
+
+                lines.extend(csv_line(columns.iter().map(|column| {
+                    record.get(column).map(format_field).unwrap_or_default()
+                })));
+
+                writer
+                    .write_all(&lines)
+                    .unwrap_or_else(|error| panic!("could not write to {path}: {error}"));
+                *bytes_written += lines.len() as u64;
+                false
+            }
+            SinkWriter::Parquet { records, .. } => {
+                records.push(record);
+                records.len() == PARQUET_BATCH_RECORDS
+            }
+        };
+
+        if batch_full {
+            self.flush_parquet();
+        }
+    }
+
+    /// The size of the file, which for Parquet leaves out records that are still buffered.
+    fn bytes_written(&self) -> u64 {
+        match &self.writer {
+            SinkWriter::Json { bytes_written, .. } | SinkWriter::Csv { bytes_written, .. } => {
+                *bytes_written
+            }
+            SinkWriter::Parquet { writer, .. } => writer.as_deref().map_or(0, |(writer, _)| {
+                (writer.bytes_written() + writer.in_progress_size()) as u64
+            }),
+        }
+    }
+
+    /// Writes the buffered records of a Parquet file, first creating its writer with the
+    /// schema inferred from them. Records that do not match the schema are left out.
+    fn flush_parquet(&mut self) {
+        let path = &self.path;
+
+        let SinkWriter::Parquet {
+            file,
+            writer,
+            records,
+        } = &mut self.writer
+        else {
+            return;
+        };
+
+        if records.is_empty() {
+            return;
+        }
+
+        let (writer, reader_builder) = &mut **writer.get_or_insert_with(|| {
+            let schema = Arc::new(
+                arrow_json::reader::infer_json_schema_from_iterator(
+                    records
+                        .iter()
+                        .map(|record| Ok(serde_json::Value::Object(record.clone()))),
+                )
+                .unwrap_or_else(|error| panic!("could not infer the schema of {path}: {error}")),
+            );
+
+            Box::new((
+                ArrowWriter::try_new(file.take().unwrap(), schema.clone(), None).unwrap(),
+                // Columns inferred as strings from values of mixed types also take the numbers
+                // and booleans of later records.
+                arrow_json::ReaderBuilder::new(schema).with_coerce_primitive(true),
+            ))
+        });
+
+        // One record that does not match the schema fails the whole batch, so the records are
+        // then written one at a time, leaving out only those that fail.
+        if write_batch(path, writer, reader_builder, records).is_err() {
+            for record in records.iter() {
+                if let Err(error) =
+                    write_batch(path, writer, reader_builder, std::slice::from_ref(record))
+                {
+                    eprintln!("could not write a record to {path}: {error}");
+                }
+            }
+        }
+
+        records.clear();
+    }
+
+    fn close(mut self) {
+        self.flush_parquet();
+
+        let result = match self.writer {
+            SinkWriter::Json { mut writer, .. } | SinkWriter::Csv { mut writer, .. } => {
+                writer.flush().map_err(|error| error.to_string())
+            }
+            SinkWriter::Parquet { writer, .. } => match writer.map(|writer| *writer) {
+                Some((writer, _)) => writer
+                    .close()
+                    .map(|_| ())
+                    .map_err(|error| error.to_string()),
+                None => Ok(()),
+            },
+        };
+
+        if let Err(error) = result {
+            panic!("could not write to {}: {error}", self.path);
+        }
+    }
+}
+
+/// Writes the records to the Parquet file as one batch, failing if any of them does not match
+/// its schema. A decoder keeps the records of a batch it failed to decode, so every batch gets
+/// a new one.
+fn write_batch(
+    path: &str,
+    writer: &mut ArrowWriter<File>,
+    reader_builder: &arrow_json::ReaderBuilder,
+    records: &[Record],
+) -> Result<(), String> {
+    let mut decoder = reader_builder
+        .clone()
+        .build_decoder()
+        .map_err(|error| error.to_string())?;
+
+    let batch = decoder
+        .serialize(records)
+        .and_then(|_| decoder.flush())
+        .map_err(|error| error.to_string())?;
+
+    if let Some(batch) = batch {
+        writer
+            .write(&batch)
+            .unwrap_or_else(|error| panic!("could not write to {path}: {error}"));
+    }
+
+    Ok(())
+}
+
+/// Inserts the time into the file name of the path, before its extension, as in
+/// `events-20240101T120000.000000Z.json`.
+fn rotated_path(path: &str) -> String {
+    let path = Path::new(path);
+    let time = chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+
+    let file_name = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => format!(
+            "{}-{time}.{}",
+            stem.to_string_lossy(),
+            extension.to_string_lossy()
+        ),
+        _ => format!("{}-{time}", path.to_string_lossy()),
+    };
+
+    path.with_file_name(file_name).to_string_lossy().to_string()
+}
+
+fn csv_line(fields: impl IntoIterator<Item = impl AsRef<[u8]>>) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields).unwrap();
+    writer.into_inner().unwrap()
+}
+
+fn read_csv_header(path: &str) -> Vec<String> {
+    csv::Reader::from_path(path)
+        .and_then(|mut reader| reader.headers().cloned())
+        .unwrap_or_else(|error| panic!("could not read the header line of {path}: {error}"))
+        .iter()
+        .map(str::to_string)
+        .collect()
+}
+
+pub fn execute_create_file_sink(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    path: String,
+    format: FileSinkFormat,
+    options: Options,
+) {
+    let (rotate_bytes, rotate_interval) =
+        parse_rotate_options(&relation_ident, options).unwrap_or_else(|error| panic!("{error}"));
+
+    let file_sink = FileSink {
+        path,
+        format,
+        rotate_bytes,
+        rotate_interval,
+    };
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::FileSink(file_sink),
+    );
+}
+
+/// Reads the `rotate_bytes` and `rotate_interval_ms` options of a file sink, which both need
+/// to be positive.
+fn parse_rotate_options(
+    relation_ident: &str,
+    options: Options,
+) -> Result<(Option<u64>, Option<Duration>), String> {
+    let (mut rotate_bytes, mut rotate_interval) = (None, None);
+
+    for (key, value) in options {
+        match key.as_str() {
+            "rotate_bytes" => {
+                rotate_bytes = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|rotate_bytes| *rotate_bytes > 0)
+                        .ok_or_else(|| format!("invalid rotate_bytes {value}"))?,
+                )
+            }
+            "rotate_interval_ms" => {
+                rotate_interval = Some(Duration::from_millis(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|rotate_interval| *rotate_interval > 0)
+                        .ok_or_else(|| format!("invalid rotate_interval_ms {value}"))?,
+                ))
+            }
+            _ => return Err(format!("unrecognized option {key} for {relation_ident}")),
+        }
+    }
+
+    Ok((rotate_bytes, rotate_interval))
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use rand::{rngs::SmallRng, seq::IndexedRandom, RngExt};
+use tokio::{sync::mpsc, time::Instant};
+
+use crate::{
+    definitions::{self, Catalog, FieldGenerator, Message, Record},
+    sql::{Options, Watermark},
+};
+
+/// How long to wait when the records due at the rate of a generator have been generated.
+const GENERATE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Generates records until `rows` have been generated, if given. With a rate, the records due
+/// since the start are generated in bursts every `GENERATE_INTERVAL`, so that the rate is kept
+/// on average even when sending is held back for a while.
+pub async fn execute_generate(
+    fields: Vec<(String, FieldGenerator)>,
+    rate: Option<u64>,
+    rows: Option<u64>,
+    sender: mpsc::Sender<Message>,
+) {
+    let mut rng: SmallRng = rand::make_rng();
+    let started_at = Instant::now();
+    let mut generated: u64 = 0;
+
+    while rows.is_none_or(|rows| generated < rows) {
+        if let Some(rate) = rate {
+            let due = (started_at.elapsed().as_secs_f64() * rate as f64) as u64;
+
+            if generated >= due {
+                tokio::time::sleep(GENERATE_INTERVAL).await;
+                continue;
+            }
+        }
+
+        generated += 1;
+
+        let record = Record::from_iter(fields.iter().map(|(name, field_generator)| {
+            let value = match field_generator {
+                FieldGenerator::Sequence => serde_json::Value::from(generated),
+                FieldGenerator::Int(min, max) => {
+                    serde_json::Value::from(rng.random_range(*min..=*max))
+                }
+                FieldGenerator::String(values) => {
+                    serde_json::Value::from(values.choose(&mut rng).unwrap().as_str())
+                }
+                FieldGenerator::Timestamp => {
+                    serde_json::Value::from(chrono::Utc::now().timestamp_millis())
+                }
+            };
+
+            (name.clone(), value)
+        }));
+
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+}
+
+pub fn execute_create_generator_source(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    options: Options,
+    watermark: Option<Watermark>,
+) {
+    let generator_source = plan_generator_source(&relation_ident, options, watermark)
+        .unwrap_or_else(|error| panic!("{error}"));
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::GeneratorSource(generator_source),
+    );
+}
+
+/// Reads the `fields`, `rate` and `rows` options of a generator, of which only `fields` is
+/// required.
+fn plan_generator_source(
+    relation_ident: &str,
+    options: Options,
+    watermark: Option<Watermark>,
+) -> Result<definitions::GeneratorSource, String> {
+    let mut fields = None;
+    let mut rate = None;
+    let mut rows = None;
+
+    for (key, value) in options {
+        match key.as_str() {
+            "fields" => fields = Some(FieldGenerator::parse_fields(&value)?),
+            "rate" => {
+                rate = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|rate| *rate > 0)
+                        .ok_or_else(|| format!("invalid rate {value}"))?,
+                )
+            }
+            "rows" => rows = Some(value.parse().map_err(|_| format!("invalid rows {value}"))?),
+            _ => return Err(format!("unrecognized option {key} for {relation_ident}")),
+        }
+    }
+
+    Ok(definitions::GeneratorSource {
+        fields: fields.ok_or_else(|| format!("fields needs to be given for {relation_ident}"))?,
+        rate,
+        rows,
+        watermark,
+    })
+}
@@ -0,0 +1,248 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use hyper::{
+    body::HttpBody,
+    header::{HeaderValue, CONNECTION},
+    server::conn::Http,
+    service::service_fn,
+    Body, Method, Request, Response, StatusCode,
+};
+use tokio::{net::TcpListener, sync::mpsc};
+
+use crate::{
+    definitions::{self, Catalog, Message, Record},
+    sql::{ErrorPolicy, Format, Watermark},
+};
+
+use super::{
+    dead_letters::{check_error_policy, ErrorHandler, PayloadDecoder},
+    decoders::{check_line_format, new_decoder},
+    file::LINE_NUMBER,
+    socket::{MAX_LINE_BYTES, REMOTE_ADDRESS},
+};
+
+/// Serves HTTP/1 requests on the address, reading the lines of the body of every POST request.
+/// Requests are handled by the connections, which report a body that fails the query back to
+/// the source.
+pub async fn execute_read_http(
+    address: String,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|error| format!("could not listen on {address}: {error}"))?;
+
+    let mut connections = FuturesUnordered::new();
+    let (failure_sender, mut failure_receiver) = mpsc::channel(1);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, remote_address)) => {
+                    let format = format.clone();
+                    let error_handler = error_handler.clone();
+                    let sender = sender.clone();
+                    let failure_sender = failure_sender.clone();
+
+                    let service = service_fn(move |request| {
+                        handle_request(
+                            request,
+                            remote_address,
+                            format.clone(),
+                            error_handler.clone(),
+                            sender.clone(),
+                            failure_sender.clone(),
+                        )
+                    });
+
+                    connections.push(
+                        Http::new()
+                            .http1_only(true)
+                            .serve_connection(stream, service),
+                    );
+                }
+                Err(error) => eprintln!("could not accept a connection on {address}: {error}"),
+            },
+            Some(result) = connections.next(), if !connections.is_empty() => {
+                if let Err(error) = result {
+                    eprintln!("could not serve a connection on {address}: {error}");
+                }
+            }
+            Some(error) = failure_receiver.recv() => return Err(error),
+        }
+    }
+}
+
+/// The largest body read from a request.
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Responds with 204 No Content only once the channel has accepted every record of the body,
+/// so that clients waiting for the response are held back while the query is behind. A body
+/// larger than `MAX_BODY_BYTES`, or with a line longer than `MAX_LINE_BYTES`, is answered with
+/// 413 Payload Too Large and the connection is closed; lines read before that are kept. A line
+/// that fails the query is answered with 400 Bad Request and sent to `failure_sender`.
+async fn handle_request(
+    request: Request<Body>,
+    remote_address: SocketAddr,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+    failure_sender: mpsc::Sender<String>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    if request
+        .body()
+        .size_hint()
+        .exact()
+        .is_some_and(|length| length > MAX_BODY_BYTES)
+    {
+        return Ok(payload_too_large_response());
+    }
+
+    let mut decoder = PayloadDecoder::new(
+        new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+        error_handler,
+    );
+    let mut body = request.into_body();
+    // The body read so far that does not end in a newline yet.
+    let mut pending = Vec::new();
+    let mut line_number = 0;
+    let mut body_bytes = 0;
+
+    loop {
+        let chunk = match body.data().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(error)) => {
+                eprintln!("could not read the body of a request from {remote_address}: {error}");
+                return Ok(empty_response(StatusCode::BAD_REQUEST));
+            }
+            None => break,
+        };
+
+        body_bytes += chunk.len() as u64;
+
+        if body_bytes > MAX_BODY_BYTES {
+            return Ok(payload_too_large_response());
+        }
+
+        pending.extend_from_slice(&chunk);
+
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            line_number += 1;
+
+            if let Err(error) =
+                send_line(&mut decoder, &line, remote_address, line_number, &sender).await
+            {
+                return Ok(fail_request(error, &failure_sender));
+            }
+        }
+
+        if pending.len() > MAX_LINE_BYTES {
+            return Ok(payload_too_large_response());
+        }
+    }
+
+    if !pending.is_empty() {
+        if let Err(error) = send_line(
+            &mut decoder,
+            &pending,
+            remote_address,
+            line_number + 1,
+            &sender,
+        )
+        .await
+        {
+            return Ok(fail_request(error, &failure_sender));
+        }
+    }
+
+    Ok(empty_response(StatusCode::NO_CONTENT))
+}
+
+/// Only the first error is kept when several requests fail the query at once.
+fn fail_request(error: String, failure_sender: &mpsc::Sender<String>) -> Response<Body> {
+    failure_sender.try_send(error).ok();
+    empty_response(StatusCode::BAD_REQUEST)
+}
+
+async fn send_line(
+    decoder: &mut PayloadDecoder,
+    line: &[u8],
+    remote_address: SocketAddr,
+    line_number: u64,
+    sender: &mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let content = line.strip_suffix(b"\n").map_or(line, |content| {
+        content.strip_suffix(b"\r").unwrap_or(content)
+    });
+
+    if content.trim_ascii().is_empty() {
+        return Ok(());
+    }
+
+    let metadata = Record::from_iter([
+        (
+            REMOTE_ADDRESS.to_string(),
+            serde_json::Value::from(remote_address.to_string()),
+        ),
+        (
+            LINE_NUMBER.to_string(),
+            serde_json::Value::from(line_number),
+        ),
+    ]);
+
+    for record in decoder.decode(content, &metadata).await? {
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+
+    Ok(())
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// The rest of the body is not read, so the connection is closed after the response.
+fn payload_too_large_response() -> Response<Body> {
+    let mut response = empty_response(StatusCode::PAYLOAD_TOO_LARGE);
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+    response
+}
+
+pub fn execute_create_http_source(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    address: String,
+    format: Format,
+    error_policy: ErrorPolicy,
+    watermark: Option<Watermark>,
+) {
+    if let Err(error) = check_line_format(&format).and_then(|_| new_decoder(&format)) {
+        panic!("{error}");
+    }
+
+    check_error_policy(catalog, &error_policy).unwrap_or_else(|error| panic!("{error}"));
+
+    let http_source = definitions::HttpSource {
+        address,
+        format,
+        error_policy,
+        watermark,
+    };
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::HttpSource(http_source),
+    );
+}
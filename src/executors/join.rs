@@ -0,0 +1,563 @@
+use std::collections::{BTreeSet, HashMap};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    definitions::{Catalog, Message, Record},
+    planners::TimeBounds,
+    sql::{Expr, JoinType},
+};
+
+use super::{evaluate_expr, event_time};
+
+/// Symmetric hash join: every record is first probed against the records of the other side
+/// with the same join key and then kept for the records of the other side still to come.
+/// Unmatched records of the outer side(s) are emitted once both inputs have ended.
+///
+/// With time bounds, a record is forgotten as soon as the watermark of the other input shows
+/// that no record within its bounds can arrive anymore, and unmatched records of outer sides
+/// are emitted at that point. Records of outer sides without a join key or event time never
+/// match, so they are emitted right away.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_hash_join(
+    catalog: &Catalog,
+    join_type: JoinType,
+    left_keys: Vec<Expr>,
+    right_keys: Vec<Expr>,
+    time_bounds: Option<TimeBounds>,
+    condition: Option<Expr>,
+    mut left_receiver: mpsc::Receiver<Message>,
+    mut right_receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+) {
+    let is_interval = time_bounds.is_some();
+    let mut left_side = JoinSide::new(
+        matches!(join_type, JoinType::Left | JoinType::Full),
+        is_interval,
+    );
+    let mut right_side = JoinSide::new(
+        matches!(join_type, JoinType::Right | JoinType::Full),
+        is_interval,
+    );
+
+    let mut watermarks = JoinWatermarks::default();
+
+    let (mut left_open, mut right_open) = (true, true);
+
+    loop {
+        tokio::select! {
+            message = left_receiver.recv(), if left_open => match message {
+                Some(Message::Record(left_record)) => {
+                    let left_time = time_bounds
+                        .as_ref()
+                        .and_then(|time_bounds| {
+                            event_time(&evaluate_expr(catalog, &left_record, &time_bounds.left_time))
+                        });
+                    let key = join_key(catalog, &left_keys, &left_record)
+                        .filter(|_| time_bounds.is_none() || left_time.is_some());
+                    let mut matched = false;
+
+                    for right_record in right_side.candidates(&key) {
+                        if !within_bounds(&time_bounds, left_time, right_record.time) {
+                            continue;
+                        }
+
+                        let mut record = left_record.clone();
+                        record.extend(right_record.record.clone());
+
+                        if matches_condition(catalog, &condition, &record) {
+                            right_record.matched = true;
+                            matched = true;
+                            sender.send(Message::Record(record)).await.unwrap();
+                        }
+                    }
+
+                    let unmatchable = left_side.insert(key, left_time, left_record, matched);
+
+                    if let Some(left_record) = unmatchable {
+                        let record = pad_record(&left_record, &right_side.columns);
+                        sender.send(Message::Record(record)).await.unwrap();
+                    }
+                }
+                Some(Message::Watermark(watermark)) => {
+                    if let Some(watermark) = watermarks.advance_left(watermark) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+
+                    evict_right(&time_bounds, &mut right_side, &left_side, watermarks.left, &sender).await;
+                }
+                None => {
+                    left_open = false;
+
+                    // An ended input no longer holds back the watermark of the other one.
+                    if let Some(watermark) = watermarks.advance_left(i64::MAX) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+
+                    evict_right(&time_bounds, &mut right_side, &left_side, watermarks.left, &sender).await;
+                }
+            },
+            message = right_receiver.recv(), if right_open => match message {
+                Some(Message::Record(right_record)) => {
+                    let right_time = time_bounds
+                        .as_ref()
+                        .and_then(|time_bounds| {
+                            event_time(&evaluate_expr(catalog, &right_record, &time_bounds.right_time))
+                        });
+                    let key = join_key(catalog, &right_keys, &right_record)
+                        .filter(|_| time_bounds.is_none() || right_time.is_some());
+                    let mut matched = false;
+
+                    for left_record in left_side.candidates(&key) {
+                        if !within_bounds(&time_bounds, left_record.time, right_time) {
+                            continue;
+                        }
+
+                        let mut record = left_record.record.clone();
+                        record.extend(right_record.clone());
+
+                        if matches_condition(catalog, &condition, &record) {
+                            left_record.matched = true;
+                            matched = true;
+                            sender.send(Message::Record(record)).await.unwrap();
+                        }
+                    }
+
+                    let unmatchable = right_side.insert(key, right_time, right_record, matched);
+
+                    if let Some(right_record) = unmatchable {
+                        let record = pad_record(&right_record, &left_side.columns);
+                        sender.send(Message::Record(record)).await.unwrap();
+                    }
+                }
+                Some(Message::Watermark(watermark)) => {
+                    if let Some(watermark) = watermarks.advance_right(watermark) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+
+                    evict_left(&time_bounds, &mut left_side, &right_side, watermarks.right, &sender).await;
+                }
+                None => {
+                    right_open = false;
+
+                    if let Some(watermark) = watermarks.advance_right(i64::MAX) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+
+                    evict_left(&time_bounds, &mut left_side, &right_side, watermarks.right, &sender).await;
+                }
+            },
+            else => break,
+        }
+    }
+
+    for left_record in left_side.unmatched() {
+        let record = pad_record(left_record, &right_side.columns);
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+
+    for right_record in right_side.unmatched() {
+        let record = pad_record(right_record, &left_side.columns);
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+}
+
+/// Forgets the left records that no right record at or after the right watermark can match.
+async fn evict_left(
+    time_bounds: &Option<TimeBounds>,
+    left_side: &mut JoinSide,
+    right_side: &JoinSide,
+    right_watermark: i64,
+    sender: &mpsc::Sender<Message>,
+) {
+    let Some(time_bounds) = time_bounds else {
+        return;
+    };
+
+    for left_record in
+        left_side.evict(|left_time| left_time.saturating_add(time_bounds.upper) < right_watermark)
+    {
+        let record = pad_record(&left_record, &right_side.columns);
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+}
+
+/// Forgets the right records that no left record at or after the left watermark can match.
+async fn evict_right(
+    time_bounds: &Option<TimeBounds>,
+    right_side: &mut JoinSide,
+    left_side: &JoinSide,
+    left_watermark: i64,
+    sender: &mpsc::Sender<Message>,
+) {
+    let Some(time_bounds) = time_bounds else {
+        return;
+    };
+
+    for right_record in
+        right_side.evict(|right_time| right_time.saturating_sub(time_bounds.lower) < left_watermark)
+    {
+        let record = pad_record(&right_record, &left_side.columns);
+        sender.send(Message::Record(record)).await.unwrap();
+    }
+}
+
+/// Times too far apart for their difference to fit are never within the bounds, which do.
+fn within_bounds(
+    time_bounds: &Option<TimeBounds>,
+    left_time: Option<i64>,
+    right_time: Option<i64>,
+) -> bool {
+    match (time_bounds, left_time, right_time) {
+        (None, _, _) => true,
+        (Some(time_bounds), Some(left_time), Some(right_time)) => {
+            right_time.checked_sub(left_time).is_some_and(|difference| {
+                (time_bounds.lower..=time_bounds.upper).contains(&difference)
+            })
+        }
+        _ => false,
+    }
+}
+
+struct JoinRecord {
+    record: Record,
+    /// Event time of the record in an interval join.
+    time: Option<i64>,
+    matched: bool,
+}
+
+struct JoinSide {
+    /// Records by the serialized values of their join keys.
+    records: HashMap<String, Vec<JoinRecord>>,
+    /// Records with a NULL join key, which never match but are emitted by outer joins.
+    unmatchable_records: Vec<Record>,
+    /// Every column seen on this side, to fill with NULL when padding the other side.
+    columns: BTreeSet<String>,
+    is_outer: bool,
+    /// An interval join emits the records that never match right away instead of keeping
+    /// them until both inputs end.
+    is_interval: bool,
+}
+
+impl JoinSide {
+    fn new(is_outer: bool, is_interval: bool) -> JoinSide {
+        JoinSide {
+            records: HashMap::new(),
+            unmatchable_records: Vec::new(),
+            columns: BTreeSet::new(),
+            is_outer,
+            is_interval,
+        }
+    }
+
+    fn candidates(&mut self, key: &Option<String>) -> impl Iterator<Item = &mut JoinRecord> {
+        key.as_ref()
+            .and_then(|key| self.records.get_mut(key))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Keeps the record for the records of the other side still to come. Returns a record
+    /// without a key instead when it has to be emitted unmatched right away.
+    fn insert(
+        &mut self,
+        key: Option<String>,
+        time: Option<i64>,
+        record: Record,
+        matched: bool,
+    ) -> Option<Record> {
+        self.columns.extend(record.keys().cloned());
+
+        match key {
+            Some(key) => self.records.entry(key).or_default().push(JoinRecord {
+                record,
+                time,
+                matched,
+            }),
+            None if self.is_outer && self.is_interval => return Some(record),
+            None if self.is_outer => self.unmatchable_records.push(record),
+            None => {}
+        }
+
+        None
+    }
+
+    /// Removes the records whose event time satisfies `is_expired`, returning those that have
+    /// to be emitted unmatched.
+    fn evict(&mut self, is_expired: impl Fn(i64) -> bool) -> Vec<Record> {
+        let mut unmatched_records = Vec::new();
+
+        self.records.retain(|_, join_records| {
+            join_records.retain_mut(|join_record| {
+                if !join_record.time.is_some_and(&is_expired) {
+                    return true;
+                }
+
+                if self.is_outer && !join_record.matched {
+                    unmatched_records.push(std::mem::take(&mut join_record.record));
+                }
+
+                false
+            });
+
+            !join_records.is_empty()
+        });
+
+        unmatched_records
+    }
+
+    fn unmatched(&self) -> Vec<&Record> {
+        if !self.is_outer {
+            return Vec::new();
+        }
+
+        self.records
+            .values()
+            .flatten()
+            .filter(|join_record| !join_record.matched)
+            .map(|join_record| &join_record.record)
+            .chain(self.unmatchable_records.iter())
+            .collect()
+    }
+}
+
+/// Serializes the values of the join keys of a record, or returns `None` if any of them is
+/// NULL since NULL is never equal to anything.
+fn join_key(catalog: &Catalog, keys: &[Expr], record: &Record) -> Option<String> {
+    let values = keys
+        .iter()
+        .map(|expr| match evaluate_expr(catalog, record, expr) {
+            serde_json::Value::Null => None,
+            // Numbers are compared by value, as with `=`.
+            serde_json::Value::Number(number) => {
+                Some(serde_json::Value::from(number.as_f64().unwrap()))
+            }
+            value => Some(value),
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(serde_json::to_string(&values).unwrap())
+}
+
+fn matches_condition(catalog: &Catalog, condition: &Option<Expr>, record: &Record) -> bool {
+    match condition {
+        Some(condition) => {
+            evaluate_expr(catalog, record, condition) == serde_json::Value::Bool(true)
+        }
+        None => true,
+    }
+}
+
+fn pad_record(record: &Record, columns: &BTreeSet<String>) -> Record {
+    let mut padded_record = record.clone();
+
+    for column in columns {
+        if !padded_record.contains_key(column) {
+            padded_record.insert(column.clone(), serde_json::Value::Null);
+        }
+    }
+
+    padded_record
+}
+
+/// Tracks the watermarks of both inputs of a join. The output watermark is the smaller of the
+/// two, since either input may still produce records up to its own watermark.
+#[derive(Debug)]
+pub struct JoinWatermarks {
+    left: i64,
+    right: i64,
+}
+
+impl Default for JoinWatermarks {
+    fn default() -> Self {
+        JoinWatermarks {
+            left: i64::MIN,
+            right: i64::MIN,
+        }
+    }
+}
+
+impl JoinWatermarks {
+    /// Returns the new output watermark if advancing the left watermark moved it forward.
+    pub fn advance_left(&mut self, watermark: i64) -> Option<i64> {
+        let previous = self.current();
+        self.left = self.left.max(watermark);
+        Some(self.current()).filter(|current| *current > previous)
+    }
+
+    /// Returns the new output watermark if advancing the right watermark moved it forward.
+    pub fn advance_right(&mut self, watermark: i64) -> Option<i64> {
+        let previous = self.current();
+        self.right = self.right.max(watermark);
+        Some(self.current()).filter(|current| *current > previous)
+    }
+
+    pub fn current(&self) -> i64 {
+        self.left.min(self.right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::definitions::OutputFormat;
+
+    fn time_bounds(lower: i64, upper: i64) -> Option<TimeBounds> {
+        Some(TimeBounds {
+            left_time: Expr::Ident("a.ts".to_string()),
+            right_time: Expr::Ident("b.ts".to_string()),
+            lower,
+            upper,
+        })
+    }
+
+    fn record(id: i64) -> Record {
+        Record::from_iter([("id".to_string(), serde_json::Value::from(id))])
+    }
+
+    #[test]
+    fn checks_the_time_bounds() {
+        let bounds = time_bounds(-10, 20);
+
+        assert!(within_bounds(&bounds, Some(100), Some(90)));
+        assert!(within_bounds(&bounds, Some(100), Some(120)));
+        assert!(!within_bounds(&bounds, Some(100), Some(89)));
+        assert!(!within_bounds(&bounds, Some(100), Some(121)));
+        // Records without an event time never match in an interval join.
+        assert!(!within_bounds(&bounds, None, Some(100)));
+        assert!(!within_bounds(&bounds, Some(100), None));
+        assert!(within_bounds(&None, None, None));
+    }
+
+    #[test]
+    fn times_too_far_apart_are_out_of_bounds() {
+        let bounds = time_bounds(i64::MIN, i64::MAX);
+
+        assert!(!within_bounds(&bounds, Some(i64::MIN), Some(i64::MAX)));
+        assert!(!within_bounds(&bounds, Some(i64::MAX), Some(-2)));
+        assert!(within_bounds(&bounds, Some(i64::MAX), Some(-1)));
+    }
+
+    #[test]
+    fn evicts_the_expired_records_of_an_outer_side() {
+        let mut side = JoinSide::new(true, true);
+
+        side.insert(Some("1".to_string()), Some(10), record(1), false);
+        side.insert(Some("1".to_string()), Some(30), record(2), false);
+        side.insert(Some("2".to_string()), Some(20), record(3), true);
+
+        // Only the unmatched records of an outer side are emitted.
+        assert_eq!(side.evict(|time| time < 25), [record(1)]);
+        assert_eq!(side.unmatched(), [&record(2)]);
+        assert_eq!(side.records.len(), 1);
+
+        assert_eq!(side.evict(|time| time < 40), [record(2)]);
+        assert!(side.records.is_empty());
+    }
+
+    #[test]
+    fn evicts_the_records_of_an_inner_side_silently() {
+        let mut side = JoinSide::new(false, true);
+
+        side.insert(Some("1".to_string()), Some(10), record(1), false);
+
+        assert!(side.evict(|time| time < 25).is_empty());
+        assert!(side.records.is_empty());
+        assert!(side.unmatched().is_empty());
+    }
+
+    #[test]
+    fn emits_unmatchable_records_of_an_outer_side() {
+        // An interval join emits them right away, a regular one once both inputs end.
+        let mut interval_side = JoinSide::new(true, true);
+        assert_eq!(
+            interval_side.insert(None, None, record(1), false),
+            Some(record(1))
+        );
+
+        let mut side = JoinSide::new(true, false);
+        assert_eq!(side.insert(None, None, record(1), false), None);
+        assert_eq!(side.unmatched(), [&record(1)]);
+
+        let mut inner_side = JoinSide::new(false, true);
+        assert_eq!(inner_side.insert(None, None, record(1), false), None);
+        assert!(inner_side.unmatched().is_empty());
+    }
+
+    #[test]
+    fn emits_the_smaller_of_the_input_watermarks() {
+        let mut watermarks = JoinWatermarks::default();
+
+        assert_eq!(watermarks.advance_left(10), None);
+        assert_eq!(watermarks.advance_right(5), Some(5));
+        assert_eq!(watermarks.advance_right(20), Some(10));
+        // Watermarks never move back, nor is an unchanged one emitted again.
+        assert_eq!(watermarks.advance_left(8), None);
+        assert_eq!(watermarks.advance_right(30), None);
+        assert_eq!(watermarks.current(), 10);
+        assert_eq!(watermarks.advance_left(i64::MAX), Some(30));
+    }
+
+    #[tokio::test]
+    async fn emits_unmatched_records_of_an_interval_join_as_the_watermarks_pass() {
+        let catalog = Catalog {
+            relations: HashMap::new(),
+            functions: HashMap::new(),
+            output_format: OutputFormat::default(),
+        };
+        let (left_sender, left_receiver) = mpsc::channel(16);
+        let (right_sender, right_receiver) = mpsc::channel(16);
+        let (sender, mut receiver) = mpsc::channel(16);
+
+        let join = execute_hash_join(
+            &catalog,
+            JoinType::Left,
+            vec![Expr::Ident("a.id".to_string())],
+            vec![Expr::Ident("b.id".to_string())],
+            time_bounds(0, 10),
+            None,
+            left_receiver,
+            right_receiver,
+            sender,
+        );
+
+        let clicks = async move {
+            let click = |relation: &str, id: i64, ts: i64| {
+                Message::Record(Record::from_iter([
+                    (format!("{relation}.id"), serde_json::Value::from(id)),
+                    (format!("{relation}.ts"), serde_json::Value::from(ts)),
+                ]))
+            };
+
+            left_sender.send(click("a", 1, 100)).await.unwrap();
+            left_sender.send(click("a", 2, 100)).await.unwrap();
+            left_sender.send(Message::Watermark(100)).await.unwrap();
+            right_sender.send(click("b", 1, 105)).await.unwrap();
+            right_sender.send(Message::Watermark(100)).await.unwrap();
+
+            // Once the output watermark arrives, every record sent before it has been joined.
+            let matched = receiver.recv().await.unwrap();
+            let watermark = receiver.recv().await.unwrap();
+
+            // The right watermark passing 100 + 10 shows that nothing can match a.id = 2 anymore.
+            right_sender.send(Message::Watermark(111)).await.unwrap();
+            let unmatched = receiver.recv().await.unwrap();
+
+            // The join sends its last watermark once its inputs end.
+            (vec![matched, watermark, unmatched], receiver)
+        };
+
+        let ((), (messages, _receiver)) = tokio::join!(join, clicks);
+
+        let [Message::Record(matched), Message::Watermark(100), Message::Record(unmatched)] =
+            &messages[..]
+        else {
+            panic!("unexpected messages {messages:?}");
+        };
+
+        assert_eq!(matched["a.id"], 1);
+        assert_eq!(matched["b.ts"], 105);
+        assert_eq!(unmatched["a.id"], 2);
+        assert_eq!(unmatched["b.id"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,597 @@
+use std::{collections::HashMap, time::Duration};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use rdkafka::{
+    consumer::{Consumer, StreamConsumer},
+    error::KafkaError,
+    producer::{future_producer::OwnedDeliveryResult, FutureProducer, FutureRecord},
+    types::RDKafkaErrorCode,
+    ClientConfig, Message as _, Offset, TopicPartitionList,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    definitions::{self, Catalog, Message, Record, StartingOffsets},
+    sql::{ErrorPolicy, Expr, Format, Options, Watermark},
+};
+
+use super::{
+    dead_letters::{check_error_policy, ErrorHandler, PayloadDecoder},
+    decoders::{check_record_format, new_decoder},
+    evaluate_expr, format_event_time, run_blocking,
+};
+
+/// Metadata columns added to every record read from a Kafka topic.
+pub const KEY: &str = "_key";
+pub const PARTITION: &str = "_partition";
+pub const OFFSET: &str = "_offset";
+pub const TIMESTAMP: &str = "_timestamp";
+
+const DEFAULT_BOOTSTRAP_SERVERS: &str = "localhost:9092";
+
+/// librdkafka only assigns partitions to consumers with a group.
+const UNCOMMITTED_GROUP_ID: &str = "streamings";
+
+/// How long to wait for the brokers when looking up the partitions or offsets of a topic.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait when the queue of the producer is full and no delivery is pending.
+const QUEUE_FULL_DELAY: Duration = Duration::from_millis(100);
+
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_read_kafka_topic(
+    topic: String,
+    bootstrap_servers: String,
+    group_id: Option<String>,
+    partitions: Option<Vec<i32>>,
+    starting_offsets: StartingOffsets,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let mut client_config = ClientConfig::new();
+    client_config.set("bootstrap.servers", &bootstrap_servers);
+
+    // Without a consumer group the partitions are still assigned through one, which never
+    // commits any offsets.
+    match &group_id {
+        Some(group_id) => client_config.set("group.id", group_id),
+        None => client_config
+            .set("group.id", UNCOMMITTED_GROUP_ID)
+            .set("enable.auto.commit", "false"),
+    };
+
+    // Only applies to partitions without committed offsets.
+    client_config.set(
+        "auto.offset.reset",
+        match starting_offsets {
+            StartingOffsets::Latest => "latest",
+            _ => "earliest",
+        },
+    );
+
+    let consumer: StreamConsumer = client_config
+        .create()
+        .map_err(|error| format!("could not create a consumer of {topic}: {error}"))?;
+
+    let consumer = match (&group_id, partitions, &starting_offsets) {
+        (Some(_), None, StartingOffsets::Earliest | StartingOffsets::Latest) => {
+            consumer
+                .subscribe(&[&topic])
+                .map_err(|error| format!("could not subscribe to {topic}: {error}"))?;
+            consumer
+        }
+        // Partitions that are given, or start at a timestamp or given offsets, are assigned to
+        // this consumer instead of by the consumer group.
+        (_, partitions, _) => {
+            let lookup_topic = topic.clone();
+            let lookup_offsets = starting_offsets.clone();
+            let has_group = group_id.is_some();
+
+            let (consumer, assignment) = run_blocking(move || {
+                let assignment = partition_offsets(
+                    &consumer,
+                    &lookup_topic,
+                    partitions,
+                    &lookup_offsets,
+                    has_group,
+                );
+                (consumer, assignment)
+            })
+            .await;
+
+            consumer
+                .assign(&assignment?)
+                .map_err(|error| format!("could not assign partitions of {topic}: {error}"))?;
+            consumer
+        }
+    };
+
+    // Every partition gets its own decoder, as every shard of a Kinesis stream does.
+    let mut decoders = HashMap::new();
+
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("could not read from {topic}: {error}");
+                continue;
+            }
+        };
+
+        // Tombstones, messages without a payload, hold no record.
+        let Some(payload) = message.payload() else {
+            continue;
+        };
+
+        let metadata = Record::from_iter([
+            (
+                KEY.to_string(),
+                message.key().map_or(serde_json::Value::Null, |key| {
+                    serde_json::Value::from(String::from_utf8_lossy(key))
+                }),
+            ),
+            (
+                PARTITION.to_string(),
+                serde_json::Value::from(message.partition()),
+            ),
+            (
+                OFFSET.to_string(),
+                serde_json::Value::from(message.offset()),
+            ),
+            (
+                TIMESTAMP.to_string(),
+                message
+                    .timestamp()
+                    .to_millis()
+                    .map_or(serde_json::Value::Null, format_event_time),
+            ),
+        ]);
+
+        let decoder = decoders.entry(message.partition()).or_insert_with(|| {
+            PayloadDecoder::new(
+                new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+                error_handler.clone(),
+            )
+        });
+
+        for mut input in decoder.decode(payload, &metadata).await? {
+            input.extend(metadata.clone());
+
+            sender.send(Message::Record(input)).await.unwrap();
+        }
+    }
+}
+
+/// Returns the offsets reading starts at for the given partitions, or else every partition of
+/// the topic. Blocks until the brokers answer.
+fn partition_offsets(
+    consumer: &impl Consumer,
+    topic: &str,
+    partitions: Option<Vec<i32>>,
+    starting_offsets: &StartingOffsets,
+    has_group: bool,
+) -> Result<TopicPartitionList, String> {
+    let topic_partitions = list_partitions(consumer, topic)?;
+
+    if let Some(partition) = partitions
+        .iter()
+        .flatten()
+        .find(|partition| !topic_partitions.contains(partition))
+    {
+        return Err(format!("{topic} has no partition {partition}"));
+    }
+
+    let partitions = partitions.unwrap_or(topic_partitions);
+
+    let mut assignment = TopicPartitionList::new();
+
+    for partition in partitions {
+        let offset = match starting_offsets {
+            // The committed offset, or else the offset of auto.offset.reset.
+            StartingOffsets::Earliest | StartingOffsets::Latest if has_group => Offset::Stored,
+            StartingOffsets::Earliest => Offset::Beginning,
+            StartingOffsets::Latest => Offset::End,
+            // Replaced by the offset of the timestamp below.
+            StartingOffsets::AtTimestamp(timestamp) => Offset::Offset(*timestamp),
+            StartingOffsets::AtOffset(offsets) => offsets
+                .iter()
+                .find(|(offset_partition, _)| *offset_partition == partition)
+                .map_or(Offset::Beginning, |(_, offset)| Offset::Offset(*offset)),
+        };
+
+        assignment
+            .add_partition_offset(topic, partition, offset)
+            .unwrap();
+    }
+
+    if let StartingOffsets::AtTimestamp(_) = starting_offsets {
+        assignment = consumer
+            .offsets_for_times(assignment, METADATA_TIMEOUT)
+            .map_err(|error| {
+                format!("could not look up the offsets of {topic} by timestamp: {error}")
+            })?;
+    }
+
+    Ok(assignment)
+}
+
+fn list_partitions(consumer: &impl Consumer, topic: &str) -> Result<Vec<i32>, String> {
+    let metadata = consumer
+        .fetch_metadata(Some(topic), METADATA_TIMEOUT)
+        .map_err(|error| format!("could not describe {topic}: {error}"))?;
+
+    let topic_metadata = metadata
+        .topics()
+        .first()
+        .ok_or_else(|| format!("could not describe {topic}"))?;
+
+    if let Some(error) = topic_metadata.error() {
+        return Err(format!(
+            "could not describe {topic}: {}",
+            RDKafkaErrorCode::from(error)
+        ));
+    }
+
+    Ok(topic_metadata
+        .partitions()
+        .iter()
+        .map(|partition| partition.id())
+        .collect())
+}
+
+pub fn execute_create_kafka_topic(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    topic: String,
+    format: Format,
+    error_policy: ErrorPolicy,
+    options: Options,
+    watermark: Option<Watermark>,
+) -> Result<(), String> {
+    check_record_format(&format).and_then(|_| new_decoder(&format))?;
+    check_error_policy(catalog, &error_policy)?;
+
+    let mut bootstrap_servers = DEFAULT_BOOTSTRAP_SERVERS.to_string();
+    let mut group_id = None;
+    let mut partitions = None;
+    let mut starting_offsets = StartingOffsets::default();
+
+    for (key, value) in options {
+        if let Some(result) = StartingOffsets::from_option(&key, &value) {
+            starting_offsets = result?;
+            continue;
+        }
+
+        match key.as_str() {
+            "bootstrap_servers" => bootstrap_servers = value,
+            "group_id" => group_id = Some(value),
+            // Given as partition numbers separated by commas.
+            "partitions" => {
+                partitions = Some(
+                    value
+                        .split(',')
+                        .map(|partition| partition.trim().parse())
+                        .collect::<Result<Vec<i32>, _>>()
+                        .map_err(|_| format!("invalid partitions {value}"))?,
+                )
+            }
+            _ => return Err(format!("unrecognized option {key} for a Kafka topic")),
+        }
+    }
+
+    let kafka_topic = definitions::KafkaTopic {
+        topic,
+        bootstrap_servers,
+        group_id,
+        partitions,
+        starting_offsets,
+        format,
+        error_policy,
+        watermark,
+    };
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::KafkaTopic(Box::new(kafka_topic)),
+    );
+
+    Ok(())
+}
+
+/// Produces the records as JSON to the topic, with the value of the PARTITION BY expression as
+/// their key. Records without a key are spread over the partitions by the producer.
+pub async fn execute_write_kafka_topic(
+    catalog: &Catalog,
+    kafka_topic: &definitions::KafkaTopic,
+    key: Option<Expr>,
+    mut receiver: mpsc::Receiver<Message>,
+) {
+    let topic = &kafka_topic.topic;
+
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", &kafka_topic.bootstrap_servers)
+        .create()
+        .unwrap_or_else(|error| panic!("could not create a producer of {topic}: {error}"));
+
+    // The producer batches messages itself, so many are in flight while their deliveries are
+    // awaited.
+    let mut deliveries = FuturesUnordered::new();
+
+    loop {
+        let message = tokio::select! {
+            message = receiver.recv() => message,
+            Some(delivery) = deliveries.next(), if !deliveries.is_empty() => {
+                check_delivery(topic, delivery);
+                continue;
+            }
+        };
+
+        let record = match message {
+            Some(Message::Record(record)) => record,
+            Some(Message::Watermark(_)) => continue,
+            None => break,
+        };
+
+        let payload = serde_json::to_vec(&record).unwrap();
+
+        let key = key
+            .as_ref()
+            .map(|expr| match evaluate_expr(catalog, &record, expr) {
+                serde_json::Value::String(key) => key,
+                value => value.to_string(),
+            });
+
+        let mut future_record: FutureRecord<str, [u8]> =
+            FutureRecord::to(topic).payload(payload.as_slice());
+
+        if let Some(key) = &key {
+            future_record = future_record.key(key.as_str());
+        }
+
+        loop {
+            match producer.send_result(future_record) {
+                Ok(delivery) => {
+                    deliveries.push(delivery);
+                    break;
+                }
+                // The queue of the producer stays full until earlier messages are delivered.
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    future_record = returned;
+
+                    match deliveries.next().await {
+                        Some(delivery) => check_delivery(topic, delivery),
+                        None => tokio::time::sleep(QUEUE_FULL_DELAY).await,
+                    }
+                }
+                Err((error, _)) => panic!("could not produce a message to {topic}: {error}"),
+            }
+        }
+    }
+
+    while let Some(delivery) = deliveries.next().await {
+        check_delivery(topic, delivery);
+    }
+}
+
+/// A delivery fails with `Canceled` if the producer is dropped before it is delivered.
+fn check_delivery<Canceled>(topic: &str, delivery: Result<OwnedDeliveryResult, Canceled>) {
+    match delivery {
+        Ok(Ok(_)) => {}
+        Ok(Err((error, _))) => panic!("could not produce a message to {topic}: {error}"),
+        Err(_) => panic!("the producer of {topic} stopped before delivering a message"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rdkafka::mocking::MockCluster;
+
+    use super::*;
+    use crate::{
+        definitions::{KafkaTopic, OutputFormat, RelationDefinition},
+        sql::Encoding,
+    };
+
+    const RECEIVE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn new_catalog() -> Catalog {
+        Catalog {
+            relations: HashMap::new(),
+            functions: HashMap::new(),
+            output_format: OutputFormat::default(),
+        }
+    }
+
+    fn create_kafka_topic(
+        catalog: &mut Catalog,
+        bootstrap_servers: &str,
+        format: Format,
+        options: &[(&str, &str)],
+    ) -> Result<KafkaTopic, String> {
+        let mut options: Options = options
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        options.push((
+            "bootstrap_servers".to_string(),
+            bootstrap_servers.to_string(),
+        ));
+
+        execute_create_kafka_topic(
+            catalog,
+            "clicks".to_string(),
+            "clicks".to_string(),
+            format,
+            ErrorPolicy::Fail,
+            options,
+            None,
+        )?;
+
+        match catalog.relations.get("clicks") {
+            Some(RelationDefinition::KafkaTopic(kafka_topic)) => Ok((**kafka_topic).clone()),
+            _ => panic!("clicks is not a Kafka topic"),
+        }
+    }
+
+    async fn produce(catalog: &Catalog, kafka_topic: &KafkaTopic, records: Vec<Record>) {
+        let (sender, receiver) = mpsc::channel(records.len() + 1);
+
+        for record in records {
+            sender.send(Message::Record(record)).await.unwrap();
+        }
+
+        drop(sender);
+
+        let key = Expr::Ident("user".to_string());
+        execute_write_kafka_topic(catalog, kafka_topic, Some(key), receiver).await;
+    }
+
+    async fn consume(kafka_topic: &KafkaTopic, count: usize) -> Result<Vec<Record>, String> {
+        let (sender, mut receiver) = mpsc::channel(256);
+
+        let reader = tokio::spawn(execute_read_kafka_topic(
+            kafka_topic.topic.clone(),
+            kafka_topic.bootstrap_servers.clone(),
+            kafka_topic.group_id.clone(),
+            kafka_topic.partitions.clone(),
+            kafka_topic.starting_offsets.clone(),
+            kafka_topic.format.clone(),
+            ErrorHandler::Fail,
+            sender,
+        ));
+
+        let mut records = Vec::new();
+
+        while records.len() < count {
+            match tokio::time::timeout(RECEIVE_TIMEOUT, receiver.recv()).await {
+                Ok(Some(Message::Record(record))) => records.push(record),
+                Ok(Some(Message::Watermark(_))) => {}
+                // The reader only ends when it fails.
+                Ok(None) => return Err(reader.await.unwrap().unwrap_err()),
+                Err(_) => panic!("read {} of {count} records", records.len()),
+            }
+        }
+
+        reader.abort();
+        Ok(records)
+    }
+
+    fn click(id: i64, user: &str) -> Record {
+        Record::from_iter([
+            ("id".to_string(), serde_json::Value::from(id)),
+            ("user".to_string(), serde_json::Value::from(user)),
+        ])
+    }
+
+    #[tokio::test]
+    async fn reads_the_records_produced_with_their_metadata() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("clicks", 3, 1).unwrap();
+
+        let catalog = &mut new_catalog();
+        let kafka_topic = create_kafka_topic(
+            catalog,
+            &cluster.bootstrap_servers(),
+            Format::default(),
+            &[],
+        )
+        .unwrap();
+
+        let clicks = (0..6)
+            .map(|id| click(id, if id % 2 == 0 { "alice" } else { "bob" }))
+            .collect::<Vec<_>>();
+
+        produce(catalog, &kafka_topic, clicks.clone()).await;
+
+        let mut records = consume(&kafka_topic, clicks.len()).await.unwrap();
+        records.sort_by_key(|record| record["id"].as_i64());
+
+        let mut partitions = HashMap::new();
+
+        for (record, click) in records.iter().zip(&clicks) {
+            assert_eq!(record["id"], click["id"]);
+            assert_eq!(record[KEY], click["user"]);
+            assert!(record[TIMESTAMP].is_string());
+
+            // Records with the same key are produced to the same partition, in order.
+            let partition = partitions
+                .entry(record[KEY].to_string())
+                .or_insert(record[PARTITION].clone());
+            assert_eq!(record[PARTITION], *partition);
+        }
+
+        for user in ["alice", "bob"] {
+            let offsets = records
+                .iter()
+                .filter(|record| record[KEY] == user)
+                .map(|record| record[OFFSET].as_i64().unwrap())
+                .collect::<Vec<_>>();
+
+            assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[tokio::test]
+    async fn starts_at_the_given_offsets() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("clicks", 1, 1).unwrap();
+
+        let catalog = &mut new_catalog();
+        let kafka_topic = create_kafka_topic(
+            catalog,
+            &cluster.bootstrap_servers(),
+            Format::default(),
+            &[("partitions", "0"), ("start_offsets", "0:1")],
+        )
+        .unwrap();
+
+        produce(
+            catalog,
+            &kafka_topic,
+            (0..3).map(|id| click(id, "alice")).collect(),
+        )
+        .await;
+
+        let records = consume(&kafka_topic, 2).await.unwrap();
+
+        assert_eq!(records[0]["id"], 1);
+        assert_eq!(records[0][OFFSET], 1);
+        assert_eq!(records[1]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn checks_the_partitions_when_read() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("clicks", 1, 1).unwrap();
+
+        // CREATE does not connect to the brokers, so it succeeds before the topic is read.
+        let kafka_topic = create_kafka_topic(
+            &mut new_catalog(),
+            &cluster.bootstrap_servers(),
+            Format::default(),
+            &[("partitions", "0, 5")],
+        )
+        .unwrap();
+
+        assert_eq!(
+            consume(&kafka_topic, 1).await.unwrap_err(),
+            "clicks has no partition 5"
+        );
+    }
+
+    #[test]
+    fn needs_the_columns_of_csv() {
+        let format = Format {
+            encoding: Encoding::Csv(None),
+            compression: None,
+        };
+
+        let error = create_kafka_topic(&mut new_catalog(), DEFAULT_BOOTSTRAP_SERVERS, format, &[])
+            .unwrap_err();
+
+        assert!(error.contains("FORMAT CSV needs COLUMNS"), "{error}");
+    }
+}
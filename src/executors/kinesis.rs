@@ -1,22 +1,194 @@
-use futures_util::stream::StreamExt;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use nom::AsBytes;
-use rusoto_core::Region;
+use rusoto_core::{credential::StaticProvider, HttpClient, Region, RusotoError};
 use rusoto_kinesis::{
-    DescribeStreamConsumerInput, DescribeStreamInput, Kinesis, KinesisClient,
+    DeregisterStreamConsumerInput, DescribeStreamConsumerError, DescribeStreamConsumerInput,
+    DescribeStreamInput, GetRecordsError, GetRecordsInput, GetShardIteratorError,
+    GetShardIteratorInput, Kinesis, KinesisClient, PutRecordsError, PutRecordsInput,
+    PutRecordsRequestEntry, RegisterStreamConsumerInput, Shard, SubscribeToShardError,
     SubscribeToShardEventStreamItem,
 };
 use tokio::sync::mpsc;
 
-use crate::definitions::{self, Catalog, Record};
+use crate::{
+    definitions::{self, Catalog, KinesisReadMode, Message, Record, StartingPosition},
+    sql::{ErrorPolicy, Expr, Format, KinesisStreamConsumer, Options, Watermark},
+};
+
+use super::{
+    dead_letters::{check_error_policy, ErrorHandler, PayloadDecoder},
+    decoders::{check_record_format, new_decoder},
+    evaluate_expr, format_event_time, kpl,
+};
+
+/// Metadata columns added to every record read from a Kinesis stream.
+pub const PARTITION_KEY: &str = "_partition_key";
+pub const SEQUENCE_NUMBER: &str = "_sequence_number";
+/// The position of a user record within a record aggregated by the KPL, NULL otherwise.
+pub const SUB_SEQUENCE_NUMBER: &str = "_sub_sequence_number";
+pub const APPROXIMATE_ARRIVAL_TIMESTAMP: &str = "_approximate_arrival_timestamp";
+pub const SHARD_ID: &str = "_shard_id";
+
+/// Region used when neither the WITH clause nor the environment names one.
+const DEFAULT_REGION: Region = Region::EuWest1;
+
+/// How long to wait before subscribing to a shard again while its previous subscription is
+/// still in use.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// How often GetRecords is called for a shard in the polling read mode, in milliseconds.
+const DEFAULT_POLL_INTERVAL: u64 = 1000;
+
+/// The most records a GetRecords call returns in the polling read mode, which is also the
+/// limit of Kinesis.
+const DEFAULT_MAX_RECORDS: i64 = 10000;
+
+/// How often a consumer is described while waiting for it to become active.
+const CONSUMER_STATUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the shards of a stream are listed again to pick up those created by resharding.
+const SHARD_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most records and bytes of data and partition keys a PutRecords call takes, and the most
+/// bytes of a single record, which are the limits of Kinesis.
+const MAX_BATCH_RECORDS: usize = 500;
+const MAX_BATCH_BYTES: usize = 5 * 1024 * 1024;
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+/// The longest partition key Kinesis takes, in characters.
+const MAX_PARTITION_KEY_LENGTH: usize = 256;
+
+/// How long records written to a stream wait for more to fill the batch they are put in.
+const BATCH_LINGER: Duration = Duration::from_millis(100);
 
+/// How often the records of a batch that failed to be put are put again, first after
+/// `PUT_RECORDS_RETRY_DELAY` and then after twice the previous delay.
+const MAX_PUT_RECORDS_RETRIES: u32 = 8;
+const PUT_RECORDS_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How often a shard iterator that failed to be got, for example because GetShardIterator was
+/// throttled, is got again, with delays that double the same way.
+const MAX_GET_SHARD_ITERATOR_RETRIES: u32 = 8;
+const GET_SHARD_ITERATOR_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest a shard waits to call GetRecords again while it is throttled, unless its poll
+/// interval is longer.
+const MAX_GET_RECORDS_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_read_kinesis_stream(
     kinesis_stream_name: String,
-    kinesis_stream_consumer_arn: String,
-    sender: mpsc::Sender<Record>,
-) {
-    let kinesis_client = KinesisClient::new(Region::EuWest1);
+    read_mode: KinesisReadMode,
+    format: Format,
+    error_handler: ErrorHandler,
+    region: Region,
+    credentials: Option<StaticProvider>,
+    starting_position: StartingPosition,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let kinesis_client = new_kinesis_client(region, credentials);
 
-    let mut shard_ids = Vec::new();
+    let mut shards = list_shards(&kinesis_client, &kinesis_stream_name).await;
+
+    let mut started_shard_ids = HashSet::new();
+    let mut finished_shard_ids = HashSet::new();
+
+    // Closed shards hold no records after the latest one.
+    if starting_position == StartingPosition::Latest {
+        finished_shard_ids.extend(
+            shards
+                .iter()
+                .filter(|shard| shard.sequence_number_range.ending_sequence_number.is_some())
+                .map(|shard| shard.shard_id.clone()),
+        );
+    }
+
+    // Shards that exist when the scan starts begin at the starting position of the scan;
+    // shards created by later splits and merges continue where their parents ended.
+    let mut shard_starting_positions = shards
+        .iter()
+        .map(|shard| {
+            (
+                shard.shard_id.clone(),
+                shard_starting_position(&starting_position, &shard.shard_id),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut shard_readers = FuturesUnordered::new();
+
+    let mut refresh_interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + SHARD_REFRESH_INTERVAL,
+        SHARD_REFRESH_INTERVAL,
+    );
+
+    loop {
+        for shard in shards.iter() {
+            if started_shard_ids.contains(&shard.shard_id)
+                || finished_shard_ids.contains(&shard.shard_id)
+            {
+                continue;
+            }
+
+            // Records of a key move to the child shards on a split or merge, so a shard is
+            // only read once its parents that are still retained have been read to the end.
+            let parents_finished = [&shard.parent_shard_id, &shard.adjacent_parent_shard_id]
+                .into_iter()
+                .flatten()
+                .all(|parent_shard_id| {
+                    finished_shard_ids.contains(parent_shard_id)
+                        || !shards
+                            .iter()
+                            .any(|shard| shard.shard_id == *parent_shard_id)
+                });
+
+            if !parents_finished {
+                continue;
+            }
+
+            let shard_starting_position = shard_starting_positions
+                .remove(&shard.shard_id)
+                .unwrap_or_else(|| match starting_position {
+                    StartingPosition::AtTimestamp(_) => {
+                        shard_starting_position(&starting_position, &shard.shard_id)
+                    }
+                    _ => shard_starting_position(&StartingPosition::TrimHorizon, &shard.shard_id),
+                });
+
+            started_shard_ids.insert(shard.shard_id.clone());
+
+            shard_readers.push(read_shard(
+                &kinesis_client,
+                &kinesis_stream_name,
+                &read_mode,
+                &format,
+                &error_handler,
+                shard.shard_id.clone(),
+                shard_starting_position,
+                &sender,
+            ));
+        }
+
+        tokio::select! {
+            Some(result) = shard_readers.next() => {
+                finished_shard_ids.insert(result?);
+                // The children of the shard may not have been listed yet.
+                shards = list_shards(&kinesis_client, &kinesis_stream_name).await;
+            }
+            _ = refresh_interval.tick() => {
+                shards = list_shards(&kinesis_client, &kinesis_stream_name).await;
+            }
+        }
+    }
+}
+
+async fn list_shards(kinesis_client: &KinesisClient, kinesis_stream_name: &str) -> Vec<Shard> {
+    let mut shards = Vec::new();
 
     let next_token = None;
 
@@ -27,18 +199,12 @@ pub async fn execute_read_kinesis_stream(
             next_token,
             shard_filter: None,
             stream_creation_timestamp: None,
-            stream_name: Some(kinesis_stream_name),
+            stream_name: Some(kinesis_stream_name.to_string()),
         })
         .await
         .unwrap();
 
-    shard_ids.extend(
-        list_shards_output
-            .shards
-            .unwrap()
-            .iter()
-            .map(|shard| shard.shard_id.clone()),
-    );
+    shards.extend(list_shards_output.shards.unwrap());
 
     while let Some(next_token_inner) = list_shards_output.next_token {
         list_shards_output = kinesis_client
@@ -53,66 +219,404 @@ pub async fn execute_read_kinesis_stream(
             .await
             .unwrap();
 
-        shard_ids.extend(
-            list_shards_output
-                .shards
-                .unwrap()
-                .iter()
-                .map(|shard| shard.shard_id.clone()),
-        );
+        shards.extend(list_shards_output.shards.unwrap());
     }
 
-    let mut event_streams = Vec::new();
+    shards
+}
 
-    for shard_id in shard_ids.as_slice() {
-        let subscribe_to_shard_output = kinesis_client
-            .subscribe_to_shard(rusoto_kinesis::SubscribeToShardInput {
-                consumer_arn: kinesis_stream_consumer_arn.clone(),
-                shard_id: shard_id.to_string(),
-                starting_position: rusoto_kinesis::StartingPosition {
-                    sequence_number: None,
-                    timestamp: None,
-                    type_: "TRIM_HORIZON".to_string(),
-                },
-            })
-            .await
-            .unwrap();
+/// Reads a shard until it is closed and read to its end, returning its ID, or the error of a
+/// record that fails the query.
+#[allow(clippy::too_many_arguments)]
+async fn read_shard(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    read_mode: &KinesisReadMode,
+    format: &Format,
+    error_handler: &ErrorHandler,
+    shard_id: String,
+    starting_position: rusoto_kinesis::StartingPosition,
+    sender: &mpsc::Sender<Message>,
+) -> Result<String, String> {
+    let mut decoder = PayloadDecoder::new(
+        new_decoder(format).unwrap_or_else(|error| panic!("{error}")),
+        error_handler.clone(),
+    );
 
-        event_streams.push(subscribe_to_shard_output.event_stream);
+    match read_mode {
+        KinesisReadMode::EnhancedFanOut { consumer_arn } => {
+            subscribe_to_shard(
+                kinesis_client,
+                kinesis_stream_name,
+                consumer_arn,
+                shard_id,
+                starting_position,
+                &mut decoder,
+                sender,
+            )
+            .await
+        }
+        KinesisReadMode::Polling {
+            poll_interval,
+            max_records,
+        } => {
+            poll_shard(
+                kinesis_client,
+                kinesis_stream_name,
+                shard_id,
+                starting_position,
+                Duration::from_millis(*poll_interval),
+                *max_records,
+                &mut decoder,
+                sender,
+            )
+            .await
+        }
     }
+}
 
-    use futures_util::stream::select_all::select_all;
+/// Reads a shard through its enhanced fan-out subscription. A subscription ends after about
+/// five minutes, so the shard is subscribed to again after the continuation sequence number
+/// of the last event, which keeps track of progress even when no records arrive.
+///
+/// The last event of a closed shard has no continuation sequence number, which rusoto_kinesis
+/// fails to parse. So once a subscription to a closed shard ends, the rest of the shard is
+/// read by polling instead, which ends at the end of the shard.
+async fn subscribe_to_shard(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    kinesis_stream_consumer_arn: &str,
+    shard_id: String,
+    mut starting_position: rusoto_kinesis::StartingPosition,
+    decoder: &mut PayloadDecoder,
+    sender: &mpsc::Sender<Message>,
+) -> Result<String, String> {
+    loop {
+        let subscribe_to_shard_result = kinesis_client
+            .subscribe_to_shard(rusoto_kinesis::SubscribeToShardInput {
+                consumer_arn: kinesis_stream_consumer_arn.to_string(),
+                shard_id: shard_id.clone(),
+                starting_position: starting_position.clone(),
+            })
+            .await;
 
-    let mut consolidated_event_stream = select_all(event_streams);
+        let mut event_stream = match subscribe_to_shard_result {
+            Ok(subscribe_to_shard_output) => subscribe_to_shard_output.event_stream,
+            // The previous subscription of the consumer to the shard may not have ended yet.
+            Err(RusotoError::Service(SubscribeToShardError::ResourceInUse(_))) => {
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+                continue;
+            }
+            Err(error) => panic!("could not subscribe to shard {shard_id}: {error}"),
+        };
 
-    while let Some(event_stream_item) = consolidated_event_stream.next().await {
-        if let Ok(subscribe_to_shard_event_stream_item) = event_stream_item {
-            match subscribe_to_shard_event_stream_item {
-                SubscribeToShardEventStreamItem::SubscribeToShardEvent(
+        while let Some(event_stream_item) = event_stream.next().await {
+            match event_stream_item {
+                Ok(SubscribeToShardEventStreamItem::SubscribeToShardEvent(
                     subscribe_to_shard_event,
-                ) => {
-                    for record in subscribe_to_shard_event.records.iter() {
-                        if let Ok(input) = serde_json::from_slice::<Record>(record.data.as_bytes())
-                        {
-                            sender.send(input).await.unwrap();
-                        } else {
-                            continue;
-                        }
+                )) => {
+                    send_records(
+                        &subscribe_to_shard_event.records,
+                        &shard_id,
+                        decoder,
+                        sender,
+                    )
+                    .await?;
+
+                    // The last event of a closed shard names the shards that replaced it.
+                    if subscribe_to_shard_event
+                        .child_shards
+                        .is_some_and(|child_shards| !child_shards.is_empty())
+                    {
+                        return Ok(shard_id);
                     }
+
+                    starting_position = rusoto_kinesis::StartingPosition {
+                        sequence_number: Some(
+                            subscribe_to_shard_event.continuation_sequence_number,
+                        ),
+                        timestamp: None,
+                        type_: "AFTER_SEQUENCE_NUMBER".to_string(),
+                    };
                 }
-                _ => {}
+                Ok(_) => {}
+                Err(_) => break,
             }
         }
+
+        if is_shard_closed(kinesis_client, kinesis_stream_name, &shard_id).await {
+            return poll_shard(
+                kinesis_client,
+                kinesis_stream_name,
+                shard_id,
+                starting_position,
+                Duration::from_millis(DEFAULT_POLL_INTERVAL),
+                DEFAULT_MAX_RECORDS,
+                decoder,
+                sender,
+            )
+            .await;
+        }
     }
 }
 
+/// Reads a shard by calling GetRecords every `poll_interval`, or right away while the shard is
+/// behind its latest record, backing off while GetRecords is throttled. The shard has been read
+/// to its end once GetRecords returns no next shard iterator.
+#[allow(clippy::too_many_arguments)]
+async fn poll_shard(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    shard_id: String,
+    starting_position: rusoto_kinesis::StartingPosition,
+    poll_interval: Duration,
+    max_records: i64,
+    decoder: &mut PayloadDecoder,
+    sender: &mpsc::Sender<Message>,
+) -> Result<String, String> {
+    // Where to get a new shard iterator from when the current one expires. Until a record is
+    // read, a LATEST iterator resumes at the time it was got, so that the records that arrived
+    // since are not skipped.
+    let mut resume_position = match starting_position.type_.as_str() {
+        "LATEST" => rusoto_kinesis::StartingPosition {
+            sequence_number: None,
+            timestamp: Some(chrono::Utc::now().timestamp_millis() as f64 / 1000.0),
+            type_: "AT_TIMESTAMP".to_string(),
+        },
+        _ => starting_position.clone(),
+    };
+
+    let mut shard_iterator = get_shard_iterator(
+        kinesis_client,
+        kinesis_stream_name,
+        &shard_id,
+        &starting_position,
+    )
+    .await;
+    // While GetRecords is throttled, the delay before calling it again doubles from the poll
+    // interval.
+    let mut retry_delay = poll_interval;
+
+    while let Some(current_shard_iterator) = shard_iterator {
+        let get_records_result = kinesis_client
+            .get_records(GetRecordsInput {
+                limit: Some(max_records),
+                shard_iterator: current_shard_iterator.clone(),
+            })
+            .await;
+
+        let is_behind = match get_records_result {
+            Ok(get_records_output) => {
+                retry_delay = poll_interval;
+
+                send_records(&get_records_output.records, &shard_id, decoder, sender).await?;
+
+                if let Some(record) = get_records_output.records.last() {
+                    resume_position = rusoto_kinesis::StartingPosition {
+                        sequence_number: Some(record.sequence_number.clone()),
+                        timestamp: None,
+                        type_: "AFTER_SEQUENCE_NUMBER".to_string(),
+                    };
+                }
+
+                shard_iterator = get_records_output.next_shard_iterator;
+
+                get_records_output
+                    .millis_behind_latest
+                    .is_some_and(|millis_behind_latest| millis_behind_latest > 0)
+            }
+            // The read throughput of a shard is shared with every other consumer of the stream.
+            Err(RusotoError::Service(GetRecordsError::ProvisionedThroughputExceeded(_))) => {
+                shard_iterator = Some(current_shard_iterator);
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_GET_RECORDS_RETRY_DELAY.max(poll_interval));
+                continue;
+            }
+            Err(RusotoError::Service(GetRecordsError::ExpiredIterator(_))) => {
+                shard_iterator = get_shard_iterator(
+                    kinesis_client,
+                    kinesis_stream_name,
+                    &shard_id,
+                    &resume_position,
+                )
+                .await;
+                continue;
+            }
+            Err(error) => panic!("could not get records of shard {shard_id}: {error}"),
+        };
+
+        if !is_behind {
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    Ok(shard_id)
+}
+
+async fn get_shard_iterator(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    shard_id: &str,
+    starting_position: &rusoto_kinesis::StartingPosition,
+) -> Option<String> {
+    let mut retry_delay = GET_SHARD_ITERATOR_RETRY_DELAY;
+
+    for _ in 0..MAX_GET_SHARD_ITERATOR_RETRIES {
+        let get_shard_iterator_result = kinesis_client
+            .get_shard_iterator(GetShardIteratorInput {
+                shard_id: shard_id.to_string(),
+                shard_iterator_type: starting_position.type_.clone(),
+                starting_sequence_number: starting_position.sequence_number.clone(),
+                stream_name: kinesis_stream_name.to_string(),
+                timestamp: starting_position.timestamp,
+            })
+            .await;
+
+        match get_shard_iterator_result {
+            Ok(get_shard_iterator_output) => return get_shard_iterator_output.shard_iterator,
+            Err(RusotoError::Service(GetShardIteratorError::ProvisionedThroughputExceeded(_)))
+            | Err(RusotoError::HttpDispatch(_)) => {}
+            Err(error) => panic!("could not get an iterator of shard {shard_id}: {error}"),
+        }
+
+        tokio::time::sleep(retry_delay).await;
+        retry_delay *= 2;
+    }
+
+    panic!("could not get an iterator of shard {shard_id}");
+}
+
+async fn send_records(
+    records: &[rusoto_kinesis::Record],
+    shard_id: &str,
+    decoder: &mut PayloadDecoder,
+    sender: &mpsc::Sender<Message>,
+) -> Result<(), String> {
+    for record in records.iter() {
+        match kpl::deaggregate(record.data.as_bytes()) {
+            Some(user_records) => {
+                for (sub_sequence_number, user_record) in user_records.iter().enumerate() {
+                    send_record(
+                        record,
+                        &user_record.data,
+                        &user_record.partition_key,
+                        Some(sub_sequence_number),
+                        shard_id,
+                        decoder,
+                        sender,
+                    )
+                    .await?;
+                }
+            }
+            None => {
+                send_record(
+                    record,
+                    record.data.as_bytes(),
+                    &record.partition_key,
+                    None,
+                    shard_id,
+                    decoder,
+                    sender,
+                )
+                .await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends the records decoded from the data of a record, or of a user record aggregated into
+/// it, together with its metadata columns.
+async fn send_record(
+    record: &rusoto_kinesis::Record,
+    data: &[u8],
+    partition_key: &str,
+    sub_sequence_number: Option<usize>,
+    shard_id: &str,
+    decoder: &mut PayloadDecoder,
+    sender: &mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let mut metadata = Record::new();
+
+    metadata.insert(
+        PARTITION_KEY.to_string(),
+        serde_json::Value::from(partition_key),
+    );
+    metadata.insert(
+        SEQUENCE_NUMBER.to_string(),
+        serde_json::Value::from(record.sequence_number.clone()),
+    );
+    metadata.insert(
+        SUB_SEQUENCE_NUMBER.to_string(),
+        serde_json::Value::from(sub_sequence_number),
+    );
+    // Kinesis gives the arrival time in seconds since the epoch.
+    metadata.insert(
+        APPROXIMATE_ARRIVAL_TIMESTAMP.to_string(),
+        record
+            .approximate_arrival_timestamp
+            .map_or(serde_json::Value::Null, |timestamp| {
+                format_event_time((timestamp * 1000.0).round() as i64)
+            }),
+    );
+    metadata.insert(SHARD_ID.to_string(), serde_json::Value::from(shard_id));
+
+    for mut input in decoder.decode(data, &metadata).await? {
+        input.extend(metadata.clone());
+
+        sender.send(Message::Record(input)).await.unwrap();
+    }
+
+    Ok(())
+}
+
+async fn is_shard_closed(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    shard_id: &str,
+) -> bool {
+    list_shards(kinesis_client, kinesis_stream_name)
+        .await
+        .iter()
+        .find(|shard| shard.shard_id == shard_id)
+        .is_none_or(|shard| shard.sequence_number_range.ending_sequence_number.is_some())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_create_kinesis_stream(
     catalog: &mut Catalog,
     relation_ident: String,
     kinesis_stream_name: String,
-    kinesis_stream_consumer_name: String,
+    kinesis_stream_consumer: Option<KinesisStreamConsumer>,
+    format: Format,
+    error_policy: ErrorPolicy,
+    options: Options,
+    watermark: Option<Watermark>,
 ) {
-    let kinesis_client = KinesisClient::new(Region::EuWest1);
+    if let Err(error) = check_record_format(&format).and_then(|_| new_decoder(&format)) {
+        panic!("{error}");
+    }
+
+    check_error_policy(catalog, &error_policy).unwrap_or_else(|error| panic!("{error}"));
+
+    let options = parse_options(options).unwrap_or_else(|error| panic!("{error}"));
+
+    // The consumer is only looked up once the statement is known to be valid.
+    let polling_or_consumer = match (options.polling, kinesis_stream_consumer) {
+        (Some(read_mode), None) => Ok(read_mode),
+        (None, Some(kinesis_stream_consumer)) => Err(kinesis_stream_consumer),
+        (Some(_), Some(_)) => panic!("a stream read with mode = POLLING has no consumer"),
+        (None, None) => {
+            panic!("a consumer is needed unless the stream is read with mode = POLLING")
+        }
+    };
+
+    if options.deregister_consumer && polling_or_consumer.is_ok() {
+        panic!("a stream read with mode = POLLING has no consumer to deregister");
+    }
+
+    let kinesis_client = new_kinesis_client(options.region.clone(), options.credentials.clone());
 
     let stream_description = kinesis_client
         .describe_stream(DescribeStreamInput {
@@ -124,24 +628,405 @@ pub async fn execute_create_kinesis_stream(
         .unwrap()
         .stream_description;
 
-    let consumer_description = kinesis_client
-        .describe_stream_consumer(DescribeStreamConsumerInput {
-            consumer_arn: None,
-            consumer_name: Some(kinesis_stream_consumer_name),
-            stream_arn: Some(stream_description.stream_arn.clone()),
-        })
-        .await
-        .unwrap()
-        .consumer_description;
+    let read_mode = match polling_or_consumer {
+        Ok(read_mode) => read_mode,
+        Err(kinesis_stream_consumer) => KinesisReadMode::EnhancedFanOut {
+            consumer_arn: describe_consumer(
+                &kinesis_client,
+                &stream_description.stream_arn,
+                kinesis_stream_consumer,
+            )
+            .await,
+        },
+    };
 
     let kinesis_stream = definitions::KinesisStream {
         kinesis_stream_name: kinesis_stream_name.clone(),
-        kinesis_stream_arn: consumer_description.stream_arn,
-        kinesis_stream_consumer_arn: consumer_description.consumer_arn,
+        kinesis_stream_arn: stream_description.stream_arn,
+        read_mode,
+        format,
+        error_policy,
+        region: options.region,
+        credentials: options.credentials,
+        starting_position: options.starting_position,
+        deregister_consumer: options.deregister_consumer,
+        watermark,
     };
 
     catalog.relations.insert(
         relation_ident,
-        definitions::RelationDefinition::KinesisStream(kinesis_stream),
+        definitions::RelationDefinition::KinesisStream(Box::new(kinesis_stream)),
     );
 }
+
+/// Returns the ARN of the enhanced fan-out consumer, first registering it with IF NOT EXISTS
+/// if there is none of the name yet and waiting for it to become active.
+async fn describe_consumer(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_arn: &str,
+    kinesis_stream_consumer: KinesisStreamConsumer,
+) -> String {
+    let describe_stream_consumer_input = DescribeStreamConsumerInput {
+        consumer_arn: None,
+        consumer_name: Some(kinesis_stream_consumer.name.clone()),
+        stream_arn: Some(kinesis_stream_arn.to_string()),
+    };
+
+    let consumer_description = match kinesis_client
+        .describe_stream_consumer(describe_stream_consumer_input.clone())
+        .await
+    {
+        Ok(describe_stream_consumer_output) => describe_stream_consumer_output.consumer_description,
+        Err(RusotoError::Service(DescribeStreamConsumerError::ResourceNotFound(_)))
+            if kinesis_stream_consumer.if_not_exists =>
+        {
+            kinesis_client
+                .register_stream_consumer(RegisterStreamConsumerInput {
+                    consumer_name: kinesis_stream_consumer.name,
+                    stream_arn: kinesis_stream_arn.to_string(),
+                })
+                .await
+                .unwrap();
+
+            kinesis_client
+                .describe_stream_consumer(describe_stream_consumer_input.clone())
+                .await
+                .unwrap()
+                .consumer_description
+        }
+        Err(error) => panic!(
+            "could not describe consumer {}: {error}",
+            kinesis_stream_consumer.name
+        ),
+    };
+
+    let mut consumer_status = consumer_description.consumer_status;
+
+    // Shards can only be subscribed to once the consumer is active, which takes a few seconds
+    // after registering it.
+    while consumer_status == "CREATING" {
+        tokio::time::sleep(CONSUMER_STATUS_POLL_INTERVAL).await;
+
+        consumer_status = kinesis_client
+            .describe_stream_consumer(describe_stream_consumer_input.clone())
+            .await
+            .unwrap()
+            .consumer_description
+            .consumer_status;
+    }
+
+    if consumer_status != "ACTIVE" {
+        panic!(
+            "consumer {} is {consumer_status}",
+            consumer_description.consumer_name
+        );
+    }
+
+    consumer_description.consumer_arn
+}
+
+pub async fn execute_drop_kinesis_stream(catalog: &mut Catalog, relation_ident: String) {
+    let Some(definitions::RelationDefinition::KinesisStream(kinesis_stream)) =
+        catalog.relations.get(&relation_ident)
+    else {
+        panic!("{relation_ident} is not a Kinesis stream");
+    };
+
+    if let (true, KinesisReadMode::EnhancedFanOut { consumer_arn }) = (
+        kinesis_stream.deregister_consumer,
+        &kinesis_stream.read_mode,
+    ) {
+        let kinesis_client = new_kinesis_client(
+            kinesis_stream.region.clone(),
+            kinesis_stream.credentials.clone(),
+        );
+
+        kinesis_client
+            .deregister_stream_consumer(DeregisterStreamConsumerInput {
+                consumer_arn: Some(consumer_arn.clone()),
+                consumer_name: None,
+                stream_arn: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    catalog.relations.remove(&relation_ident);
+}
+
+/// The settings of a `CREATE KINESIS STREAM` statement given in its WITH clause.
+struct KinesisStreamOptions {
+    region: Region,
+    credentials: Option<StaticProvider>,
+    starting_position: StartingPosition,
+    /// The polling read mode, only set for `mode = POLLING`.
+    polling: Option<KinesisReadMode>,
+    deregister_consumer: bool,
+}
+
+/// Region and endpoint default to the `AWS_REGION` (or `AWS_DEFAULT_REGION`) and
+/// `AWS_ENDPOINT_URL` environment variables; a custom endpoint such as LocalStack's is used
+/// with the region name as given.
+fn parse_options(options: Options) -> Result<KinesisStreamOptions, String> {
+    let mut region_name = std::env::var("AWS_REGION")
+        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+        .ok();
+    let mut endpoint = std::env::var("AWS_ENDPOINT_URL").ok();
+    let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+    let mut starting_position = StartingPosition::default();
+    let mut mode = "ENHANCED_FAN_OUT".to_string();
+    let (mut poll_interval, mut max_records) = (DEFAULT_POLL_INTERVAL, DEFAULT_MAX_RECORDS);
+    let mut deregister_consumer = false;
+
+    for (key, value) in options {
+        if let Some(result) = StartingPosition::from_option(&key, &value) {
+            starting_position = result?;
+            continue;
+        }
+
+        match key.as_str() {
+            "region" => region_name = Some(value),
+            "endpoint" => endpoint = Some(value),
+            "access_key_id" => access_key_id = Some(value),
+            "secret_access_key" => secret_access_key = Some(value),
+            "session_token" => session_token = Some(value),
+            "mode" => mode = value.to_uppercase(),
+            "deregister_consumer" => {
+                deregister_consumer = value
+                    .parse()
+                    .map_err(|_| "deregister_consumer needs to be true or false".to_string())?
+            }
+            "poll_interval_ms" => {
+                poll_interval = value
+                    .parse()
+                    .ok()
+                    .filter(|poll_interval| *poll_interval > 0)
+                    .ok_or_else(|| "poll_interval_ms needs to be a positive integer".to_string())?
+            }
+            "max_records" => {
+                max_records = value
+                    .parse()
+                    .ok()
+                    .filter(|max_records| (1..=10000).contains(max_records))
+                    .ok_or_else(|| "max_records needs to be from 1 to 10000".to_string())?
+            }
+            _ => return Err(format!("unrecognized option {key} for a Kinesis stream")),
+        }
+    }
+
+    let region = match (region_name, endpoint) {
+        (region_name, Some(endpoint)) => Region::Custom {
+            name: region_name.unwrap_or_else(|| DEFAULT_REGION.name().to_string()),
+            endpoint,
+        },
+        (Some(region_name), None) => region_name.parse().map_err(|error| format!("{error}"))?,
+        (None, None) => DEFAULT_REGION,
+    };
+
+    let credentials = match (access_key_id, secret_access_key) {
+        (Some(access_key_id), Some(secret_access_key)) => Some(StaticProvider::new(
+            access_key_id,
+            secret_access_key,
+            session_token,
+            None,
+        )),
+        (None, None) => None,
+        _ => {
+            return Err("access_key_id and secret_access_key need to be given together".to_string())
+        }
+    };
+
+    let polling = match mode.as_str() {
+        "ENHANCED_FAN_OUT" => None,
+        "POLLING" => Some(KinesisReadMode::Polling {
+            poll_interval,
+            max_records,
+        }),
+        _ => {
+            return Err(format!(
+                "mode needs to be ENHANCED_FAN_OUT or POLLING, not {mode}"
+            ))
+        }
+    };
+
+    Ok(KinesisStreamOptions {
+        region,
+        credentials,
+        starting_position,
+        polling,
+        deregister_consumer,
+    })
+}
+
+fn shard_starting_position(
+    starting_position: &StartingPosition,
+    shard_id: &str,
+) -> rusoto_kinesis::StartingPosition {
+    let (type_, sequence_number, timestamp) = match starting_position {
+        StartingPosition::TrimHorizon => ("TRIM_HORIZON", None, None),
+        StartingPosition::Latest => ("LATEST", None, None),
+        // Kinesis takes timestamps in seconds since the epoch.
+        StartingPosition::AtTimestamp(timestamp) => {
+            ("AT_TIMESTAMP", None, Some(*timestamp as f64 / 1000.0))
+        }
+        StartingPosition::AtSequenceNumber(sequence_numbers) => match sequence_numbers
+            .iter()
+            .find(|(sequence_number_shard_id, _)| sequence_number_shard_id == shard_id)
+        {
+            Some((_, sequence_number)) => {
+                ("AT_SEQUENCE_NUMBER", Some(sequence_number.clone()), None)
+            }
+            None => ("TRIM_HORIZON", None, None),
+        },
+    };
+
+    rusoto_kinesis::StartingPosition {
+        sequence_number,
+        timestamp,
+        type_: type_.to_string(),
+    }
+}
+
+/// Writes the records as JSON to the stream, batched into PutRecords calls.
+pub async fn execute_write_kinesis_stream(
+    catalog: &Catalog,
+    kinesis_stream: &definitions::KinesisStream,
+    partition_key: Option<Expr>,
+    mut receiver: mpsc::Receiver<Message>,
+) {
+    let kinesis_client = new_kinesis_client(
+        kinesis_stream.region.clone(),
+        kinesis_stream.credentials.clone(),
+    );
+
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+
+    let mut linger_interval = tokio::time::interval(BATCH_LINGER);
+
+    loop {
+        let message = tokio::select! {
+            message = receiver.recv() => message,
+            _ = linger_interval.tick(), if !batch.is_empty() => {
+                let records = std::mem::take(&mut batch);
+                put_records(&kinesis_client, &kinesis_stream.kinesis_stream_name, records).await;
+                batch_bytes = 0;
+                continue;
+            }
+        };
+
+        let record = match message {
+            Some(Message::Record(record)) => record,
+            Some(Message::Watermark(_)) => continue,
+            None => break,
+        };
+
+        let data = serde_json::to_vec(&record).unwrap();
+
+        // Without an expression, records are spread over the shards by their data.
+        let partition_key = match &partition_key {
+            Some(expr) => match evaluate_expr(catalog, &record, expr) {
+                serde_json::Value::String(partition_key) => partition_key,
+                value => value.to_string(),
+            },
+            None => format!("{:x}", md5::compute(&data)),
+        };
+
+        // Kinesis takes neither empty partition keys nor those that are too long.
+        let partition_key = if partition_key.is_empty()
+            || partition_key.chars().count() > MAX_PARTITION_KEY_LENGTH
+        {
+            format!("{:x}", md5::compute(&partition_key))
+        } else {
+            partition_key
+        };
+
+        let record_bytes = data.len() + partition_key.len();
+
+        if record_bytes > MAX_RECORD_BYTES {
+            eprintln!(
+                "could not put record of {record_bytes} bytes into {}",
+                kinesis_stream.kinesis_stream_name
+            );
+            continue;
+        }
+
+        if batch.len() == MAX_BATCH_RECORDS || batch_bytes + record_bytes > MAX_BATCH_BYTES {
+            let records = std::mem::take(&mut batch);
+            put_records(
+                &kinesis_client,
+                &kinesis_stream.kinesis_stream_name,
+                records,
+            )
+            .await;
+            batch_bytes = 0;
+        }
+
+        batch.push(PutRecordsRequestEntry {
+            data: data.into(),
+            explicit_hash_key: None,
+            partition_key,
+        });
+        batch_bytes += record_bytes;
+    }
+
+    if !batch.is_empty() {
+        put_records(&kinesis_client, &kinesis_stream.kinesis_stream_name, batch).await;
+    }
+}
+
+/// Puts the records into the stream, putting those that failed to be put, for example because
+/// the write throughput of a shard was exceeded, again.
+async fn put_records(
+    kinesis_client: &KinesisClient,
+    kinesis_stream_name: &str,
+    mut records: Vec<PutRecordsRequestEntry>,
+) {
+    let mut retry_delay = PUT_RECORDS_RETRY_DELAY;
+
+    for _ in 0..MAX_PUT_RECORDS_RETRIES {
+        let put_records_result = kinesis_client
+            .put_records(PutRecordsInput {
+                records: records.clone(),
+                stream_name: kinesis_stream_name.to_string(),
+            })
+            .await;
+
+        match put_records_result {
+            Ok(put_records_output) => {
+                // The results are in the order of the records.
+                records = records
+                    .into_iter()
+                    .zip(put_records_output.records)
+                    .filter(|(_, result)| result.error_code.is_some())
+                    .map(|(record, _)| record)
+                    .collect();
+
+                if records.is_empty() {
+                    return;
+                }
+            }
+            Err(RusotoError::Service(PutRecordsError::ProvisionedThroughputExceeded(_)))
+            | Err(RusotoError::Service(PutRecordsError::KMSThrottling(_)))
+            | Err(RusotoError::HttpDispatch(_)) => {}
+            Err(error) => panic!("could not put records into {kinesis_stream_name}: {error}"),
+        }
+
+        tokio::time::sleep(retry_delay).await;
+        retry_delay *= 2;
+    }
+
+    panic!(
+        "could not put {} records into {kinesis_stream_name}",
+        records.len()
+    );
+}
+
+pub fn new_kinesis_client(region: Region, credentials: Option<StaticProvider>) -> KinesisClient {
+    match credentials {
+        Some(credentials) => {
+            KinesisClient::new_with(HttpClient::new().unwrap(), credentials, region)
+        }
+        None => KinesisClient::new(region),
+    }
+}
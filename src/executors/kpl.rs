@@ -0,0 +1,157 @@
+use prost::Message;
+
+/// The Kinesis Producer Library packs several user records into one Kinesis record as this
+/// header, a protobuf `AggregatedRecord` and the MD5 digest of the protobuf bytes.
+const MAGIC: [u8; 4] = [0xF3, 0x89, 0x9A, 0xC2];
+
+const DIGEST_LENGTH: usize = 16;
+
+#[derive(Clone, PartialEq, Message)]
+struct AggregatedRecord {
+    #[prost(string, repeated, tag = "1")]
+    partition_key_table: Vec<String>,
+    #[prost(string, repeated, tag = "2")]
+    explicit_hash_key_table: Vec<String>,
+    #[prost(message, repeated, tag = "3")]
+    records: Vec<AggregatedUserRecord>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AggregatedUserRecord {
+    #[prost(uint64, required, tag = "1")]
+    partition_key_index: u64,
+    #[prost(uint64, optional, tag = "2")]
+    explicit_hash_key_index: Option<u64>,
+    #[prost(bytes = "vec", required, tag = "3")]
+    data: Vec<u8>,
+}
+
+/// A record as put by the producer, before aggregation.
+pub struct UserRecord {
+    pub partition_key: String,
+    pub data: Vec<u8>,
+}
+
+/// Returns the user records of a KPL-aggregated record in order, so that the position of each
+/// is its sub-sequence number, or `None` if the data is not an intact aggregated record.
+pub fn deaggregate(data: &[u8]) -> Option<Vec<UserRecord>> {
+    let protobuf = data
+        .strip_prefix(&MAGIC)?
+        .get(..data.len().checked_sub(MAGIC.len() + DIGEST_LENGTH)?)?;
+    let digest = &data[data.len() - DIGEST_LENGTH..];
+
+    if md5::compute(protobuf).0 != digest {
+        return None;
+    }
+
+    let aggregated_record = AggregatedRecord::decode(protobuf).ok()?;
+
+    aggregated_record
+        .records
+        .into_iter()
+        .map(|record| {
+            let partition_key = aggregated_record
+                .partition_key_table
+                .get(usize::try_from(record.partition_key_index).ok()?)?;
+
+            Some(UserRecord {
+                partition_key: partition_key.clone(),
+                data: record.data,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs the records the way the Kinesis Producer Library does, with a partition key table
+    /// shared by the records.
+    fn aggregate(records: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut partition_key_table: Vec<String> = Vec::new();
+
+        let records = records
+            .iter()
+            .map(|(partition_key, data)| {
+                let partition_key_index = partition_key_table
+                    .iter()
+                    .position(|table_key| table_key == partition_key)
+                    .unwrap_or_else(|| {
+                        partition_key_table.push(partition_key.to_string());
+                        partition_key_table.len() - 1
+                    });
+
+                AggregatedUserRecord {
+                    partition_key_index: partition_key_index as u64,
+                    explicit_hash_key_index: None,
+                    data: data.to_vec(),
+                }
+            })
+            .collect();
+
+        let protobuf = AggregatedRecord {
+            partition_key_table,
+            explicit_hash_key_table: Vec::new(),
+            records,
+        }
+        .encode_to_vec();
+
+        [&MAGIC[..], &protobuf, &md5::compute(&protobuf).0].concat()
+    }
+
+    #[test]
+    fn deaggregates_the_user_records_in_order() {
+        let data = aggregate(&[
+            ("a", b"{\"n\":1}"),
+            ("b", b"{\"n\":2}"),
+            ("a", b"{\"n\":3}"),
+        ]);
+
+        let user_records = deaggregate(&data).unwrap();
+
+        assert_eq!(
+            user_records
+                .iter()
+                .map(|user_record| (user_record.partition_key.as_str(), &user_record.data[..]))
+                .collect::<Vec<_>>(),
+            [
+                ("a", &b"{\"n\":1}"[..]),
+                ("b", &b"{\"n\":2}"[..]),
+                ("a", &b"{\"n\":3}"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut data = aggregate(&[("a", b"{\"n\":1}")]);
+        *data.last_mut().unwrap() ^= 0xFF;
+
+        assert!(deaggregate(&data).is_none());
+    }
+
+    #[test]
+    fn rejects_a_record_that_is_not_aggregated() {
+        assert!(deaggregate(b"{\"n\":1}").is_none());
+        assert!(deaggregate(&MAGIC).is_none());
+    }
+
+    #[test]
+    fn rejects_a_partition_key_index_out_of_the_table() {
+        let protobuf = AggregatedRecord {
+            partition_key_table: vec!["a".to_string()],
+            explicit_hash_key_table: Vec::new(),
+            records: vec![AggregatedUserRecord {
+                partition_key_index: 1,
+                explicit_hash_key_index: None,
+                data: b"{\"n\":1}".to_vec(),
+            }],
+        }
+        .encode_to_vec();
+
+        let data = [&MAGIC[..], &protobuf, &md5::compute(&protobuf).0].concat();
+
+        assert!(deaggregate(&data).is_none());
+    }
+}
@@ -1,124 +1,481 @@
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::HashMap, pin::Pin, sync::Mutex};
 
 use async_recursion::async_recursion;
-use futures_util::Future;
-use tokio::sync::mpsc;
+use futures_util::{Future, FutureExt};
+use tokio::sync::{mpsc, watch};
 
 use crate::{
-    definitions::{Catalog, FunctionDefinition, Record},
+    definitions::{Catalog, FunctionDefinition, Message, OutputFormat, Record, RelationDefinition},
     planners::{self, plan_query},
-    sql::{BinaryOperator, Expr, SelectItem, Statement},
+    sql::{BinaryOperator, Expr, Statement, UnaryOperator, Watermark},
 };
 
+use self::join::JoinWatermarks;
+
+pub mod aggregate;
+pub mod dead_letters;
+pub mod decoders;
+pub mod file;
+pub mod file_sink;
+pub mod generator;
+pub mod http;
+pub mod join;
+pub mod kafka;
 pub mod kinesis;
+pub mod kpl;
+pub mod print;
+pub mod socket;
+pub mod stdin;
 
-pub async fn execute_statement<'a>(
-    catalog: &'a mut Catalog,
-    statement: Statement,
-) {
+/// The futures executing a statement, which end with an error when the statement fails.
+type PlanFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// Executes a statement, returning an error if it fails, such as a query that cannot be
+/// planned or a source with an error policy of FAIL reading a payload it cannot decode.
+pub async fn execute_statement(catalog: &mut Catalog, statement: Statement) -> Result<(), String> {
     match statement {
         Statement::Select(query) => {
-            let (sender, mut receiver) = mpsc::channel(256);
+            let (sender, receiver) = mpsc::channel(256);
+
+            let (cancel_sender, cancelled) = watch::channel(false);
 
-            let plan = plan_query(catalog, &query).unwrap();
-            let mut futures = execute_plan(catalog, plan, sender).await;
+            let plan = plan_query(catalog, &query)?;
+            let mut futures = execute_plan(catalog, plan, cancelled, sender).await;
 
-            tokio_scoped::scope(|scope| {
-                for future in futures.drain(0..) {
-                    scope.spawn(future);
+            futures.push(Box::pin(
+                print::execute_print(catalog.output_format, receiver).map(Ok),
+            ));
+
+            return run_futures(futures, &cancel_sender);
+        }
+        Statement::Insert(relation_ident, partition_key, query) => {
+            let (sender, receiver) = mpsc::channel(256);
+
+            let sink: PlanFuture = match catalog.relations.get(&relation_ident) {
+                Some(RelationDefinition::KinesisStream(kinesis_stream)) => Box::pin(
+                    kinesis::execute_write_kinesis_stream(
+                        catalog,
+                        kinesis_stream,
+                        partition_key,
+                        receiver,
+                    )
+                    .map(Ok),
+                ),
+                Some(RelationDefinition::KafkaTopic(kafka_topic)) => Box::pin(
+                    kafka::execute_write_kafka_topic(catalog, kafka_topic, partition_key, receiver)
+                        .map(Ok),
+                ),
+                Some(RelationDefinition::FileSink(_)) if partition_key.is_some() => {
+                    panic!("{relation_ident} does not take PARTITION BY")
+                }
+                Some(RelationDefinition::FileSink(file_sink)) => {
+                    Box::pin(file_sink::execute_write_file(file_sink, receiver).map(Ok))
                 }
+                _ => panic!("{relation_ident} is not a Kinesis stream, Kafka topic or file sink"),
+            };
 
-                scope.spawn(async move {
-                    while let Some(record) = receiver.recv().await {
-                        let json = serde_json::to_string(&record).unwrap();
-                        println!("{json}");
-                    }
-                });
-            });
+            let (cancel_sender, cancelled) = watch::channel(false);
+
+            let plan = plan_query(catalog, &query)?;
+            let mut futures = execute_plan(catalog, plan, cancelled, sender).await;
+
+            futures.push(sink);
+
+            return run_futures(futures, &cancel_sender);
         }
         Statement::CreateKinesisStream(
             relation_ident,
             kinesis_stream_name,
-            kinesis_stream_consumer_name,
+            kinesis_stream_consumer,
+            format,
+            error_policy,
+            options,
+            watermark,
         ) => {
             kinesis::execute_create_kinesis_stream(
                 catalog,
                 relation_ident,
                 kinesis_stream_name,
-                kinesis_stream_consumer_name,
+                kinesis_stream_consumer,
+                format,
+                error_policy,
+                options,
+                watermark,
             )
             .await
         }
+        Statement::CreateKafkaTopic(
+            relation_ident,
+            topic,
+            format,
+            error_policy,
+            options,
+            watermark,
+        ) => kafka::execute_create_kafka_topic(
+            catalog,
+            relation_ident,
+            topic,
+            format,
+            error_policy,
+            options,
+            watermark,
+        )?,
+        Statement::CreateFileSource(
+            relation_ident,
+            path,
+            format,
+            error_policy,
+            tail,
+            watermark,
+        ) => file::execute_create_file_source(
+            catalog,
+            relation_ident,
+            path,
+            format,
+            error_policy,
+            tail,
+            watermark,
+        ),
+        Statement::CreateSocketSource(
+            relation_ident,
+            protocol,
+            address,
+            format,
+            error_policy,
+            watermark,
+        ) => socket::execute_create_socket_source(
+            catalog,
+            relation_ident,
+            protocol,
+            address,
+            format,
+            error_policy,
+            watermark,
+        ),
+        Statement::CreateHttpSource(relation_ident, address, format, error_policy, watermark) => {
+            http::execute_create_http_source(
+                catalog,
+                relation_ident,
+                address,
+                format,
+                error_policy,
+                watermark,
+            )
+        }
+        Statement::CreateGeneratorSource(relation_ident, options, watermark) => {
+            generator::execute_create_generator_source(catalog, relation_ident, options, watermark)
+        }
+        Statement::CreateFileSink(relation_ident, path, format, options) => {
+            file_sink::execute_create_file_sink(catalog, relation_ident, path, format, options)
+        }
+        Statement::Set(key, value) => match key.as_str() {
+            "output_format" => {
+                catalog.output_format =
+                    OutputFormat::from_name(&value).unwrap_or_else(|error| panic!("{error}"))
+            }
+            _ => panic!("unrecognized setting {key}"),
+        },
+        Statement::DropKinesisStream(relation_ident) => {
+            kinesis::execute_drop_kinesis_stream(catalog, relation_ident).await
+        }
         Statement::Explain(query) => {
-            println!("{:#?}", plan_query(catalog, &query).unwrap());
+            println!("{:#?}", plan_query(catalog, &query)?);
         }
     }
+
+    Ok(())
+}
+
+/// Runs the futures of a statement until they have all ended. Once one of them fails, the
+/// sources are cancelled through `cancel_sender`, so that the rest end as their inputs close,
+/// and the statement fails with the first error.
+fn run_futures(
+    futures: Vec<PlanFuture>,
+    cancel_sender: &watch::Sender<bool>,
+) -> Result<(), String> {
+    let failure = Mutex::new(None);
+
+    tokio_scoped::scope(|scope| {
+        for future in futures {
+            let failure = &failure;
+
+            scope.spawn(async move {
+                if let Err(error) = future.await {
+                    failure.lock().unwrap().get_or_insert(error);
+                    cancel_sender.send_replace(true);
+                }
+            });
+        }
+    });
+
+    match failure.into_inner().unwrap() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Reads a source until it ends, or until the statement is cancelled. Only sources are
+/// cancelled, since the operators reading from them could otherwise fail to send to one
+/// dropped before them.
+async fn until_cancelled(
+    mut cancelled: watch::Receiver<bool>,
+    source: impl Future<Output = Result<(), String>>,
+) -> Result<(), String> {
+    tokio::select! {
+        result = source => result,
+        _ = cancelled.wait_for(|cancelled| *cancelled) => Ok(()),
+    }
 }
 
 #[async_recursion]
 async fn execute_plan<'a>(
     catalog: &'a Catalog,
     plan: planners::QueryPlan,
-    sender: mpsc::Sender<Record>,
-) -> Vec<Pin<Box<dyn Future<Output = ()> + Send + 'a>>> {
+    cancelled: watch::Receiver<bool>,
+    sender: mpsc::Sender<Message>,
+) -> Vec<PlanFuture<'a>> {
     let mut futures = Vec::new();
 
     match plan {
         planners::QueryPlan::Empty => (),
         planners::QueryPlan::FullJoin(left, right) => {
             let (left_sender, left_receiver) = mpsc::channel(256);
-            let mut left_futures = execute_plan(catalog, *left, left_sender).await;
+            let mut left_futures =
+                execute_plan(catalog, *left, cancelled.clone(), left_sender).await;
             futures.append(&mut left_futures);
 
             let (right_sender, right_receiver) = mpsc::channel(256);
             let mut right_futures =
-                execute_plan(catalog, *right, right_sender).await;
+                execute_plan(catalog, *right, cancelled.clone(), right_sender).await;
             futures.append(&mut right_futures);
 
-            let future = Box::pin(execute_full_join(
-                left_receiver,
-                right_receiver,
-                sender,
-            ));
+            let future = Box::pin(execute_full_join(left_receiver, right_receiver, sender).map(Ok));
             futures.push(future);
         }
+        planners::QueryPlan::Join {
+            join_type,
+            left_keys,
+            right_keys,
+            time_bounds,
+            condition,
+            left,
+            right,
+        } => {
+            let (left_sender, left_receiver) = mpsc::channel(256);
+            let mut left_futures =
+                execute_plan(catalog, *left, cancelled.clone(), left_sender).await;
+            futures.append(&mut left_futures);
+
+            let (right_sender, right_receiver) = mpsc::channel(256);
+            let mut right_futures =
+                execute_plan(catalog, *right, cancelled.clone(), right_sender).await;
+            futures.append(&mut right_futures);
+
+            futures.push(Box::pin(
+                join::execute_hash_join(
+                    catalog,
+                    join_type,
+                    left_keys,
+                    right_keys,
+                    time_bounds,
+                    condition,
+                    left_receiver,
+                    right_receiver,
+                    sender,
+                )
+                .map(Ok),
+            ));
+        }
+        planners::QueryPlan::Qualify { name, query } => {
+            let (inner_sender, inner_receiver) = mpsc::channel(256);
+
+            let mut inner_futures =
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
+
+            futures.append(&mut inner_futures);
+            futures.push(Box::pin(
+                execute_qualify(name, inner_receiver, sender).map(Ok),
+            ));
+        }
         planners::QueryPlan::KinesisStreamScan {
             kinesis_stream_name,
-            kinesis_stream_consumer_arn,
+            read_mode,
+            format,
+            error_policy,
+            region,
+            credentials,
+            starting_position,
         } => {
-            let future = Box::pin(kinesis::execute_read_kinesis_stream(
-                kinesis_stream_name,
-                kinesis_stream_consumer_arn,
-                sender,
+            let error_handler =
+                dead_letters::new_error_handler(catalog, error_policy, &mut futures);
+
+            let future = Box::pin(until_cancelled(
+                cancelled,
+                kinesis::execute_read_kinesis_stream(
+                    kinesis_stream_name,
+                    read_mode,
+                    format,
+                    error_handler,
+                    region,
+                    credentials,
+                    starting_position,
+                    sender,
+                ),
             ));
             futures.push(future);
         }
+        planners::QueryPlan::KafkaTopicScan {
+            topic,
+            bootstrap_servers,
+            group_id,
+            partitions,
+            starting_offsets,
+            format,
+            error_policy,
+        } => {
+            let error_handler =
+                dead_letters::new_error_handler(catalog, error_policy, &mut futures);
+
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                kafka::execute_read_kafka_topic(
+                    topic,
+                    bootstrap_servers,
+                    group_id,
+                    partitions,
+                    starting_offsets,
+                    format,
+                    error_handler,
+                    sender,
+                ),
+            )));
+        }
+        planners::QueryPlan::FileScan {
+            path,
+            format,
+            error_policy,
+            tail,
+        } => {
+            let error_handler =
+                dead_letters::new_error_handler(catalog, error_policy, &mut futures);
+
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                file::execute_read_file(path, format, error_handler, tail, sender),
+            )));
+        }
+        planners::QueryPlan::SocketScan {
+            protocol,
+            address,
+            format,
+            error_policy,
+        } => {
+            let error_handler =
+                dead_letters::new_error_handler(catalog, error_policy, &mut futures);
+
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                socket::execute_read_socket(protocol, address, format, error_handler, sender),
+            )));
+        }
+        planners::QueryPlan::HttpScan {
+            address,
+            format,
+            error_policy,
+        } => {
+            let error_handler =
+                dead_letters::new_error_handler(catalog, error_policy, &mut futures);
+
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                http::execute_read_http(address, format, error_handler, sender),
+            )));
+        }
+        planners::QueryPlan::GeneratorScan { fields, rate, rows } => {
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                generator::execute_generate(fields, rate, rows, sender).map(Ok),
+            )));
+        }
+        planners::QueryPlan::StdinScan { format } => {
+            futures.push(Box::pin(until_cancelled(
+                cancelled,
+                stdin::execute_read_stdin(format, sender),
+            )));
+        }
         planners::QueryPlan::Projection { items, query } => {
             let (inner_sender, inner_receiver) = mpsc::channel(256);
 
             let mut inner_futures =
-                execute_plan(catalog, *query, inner_sender).await;
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
 
             futures.append(&mut inner_futures);
-            futures.push(Box::pin(execute_projection(
-                catalog,
-                items,
-                inner_receiver,
-                sender,
-            )));
+            futures.push(Box::pin(
+                execute_projection(catalog, items, inner_receiver, sender).map(Ok),
+            ));
+        }
+        planners::QueryPlan::Aggregate {
+            group_by,
+            aggregates,
+            query,
+        } => {
+            let (inner_sender, inner_receiver) = mpsc::channel(256);
+
+            let mut inner_futures =
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
+
+            futures.append(&mut inner_futures);
+            futures.push(Box::pin(
+                aggregate::execute_aggregate(catalog, group_by, aggregates, inner_receiver, sender)
+                    .map(Ok),
+            ));
+        }
+        planners::QueryPlan::WindowAggregate {
+            window,
+            time,
+            group_by,
+            aggregates,
+            query,
+        } => {
+            let (inner_sender, inner_receiver) = mpsc::channel(256);
+
+            let mut inner_futures =
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
+
+            futures.append(&mut inner_futures);
+            futures.push(Box::pin(
+                aggregate::execute_window_aggregate(
+                    catalog,
+                    window,
+                    time,
+                    group_by,
+                    aggregates,
+                    inner_receiver,
+                    sender,
+                )
+                .map(Ok),
+            ));
         }
         planners::QueryPlan::Selection { condition, query } => {
             let (inner_sender, inner_receiver) = mpsc::channel(256);
 
             let mut inner_futures =
-                execute_plan(catalog, *query, inner_sender).await;
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
             futures.append(&mut inner_futures);
-            futures.push(Box::pin(execute_filter(
-                catalog,
-                condition,
-                inner_receiver,
-                sender,
-            )));
+            futures.push(Box::pin(
+                execute_filter(catalog, condition, inner_receiver, sender).map(Ok),
+            ));
+        }
+        planners::QueryPlan::AssignWatermarks { watermark, query } => {
+            let (inner_sender, inner_receiver) = mpsc::channel(256);
+
+            let mut inner_futures =
+                execute_plan(catalog, *query, cancelled.clone(), inner_sender).await;
+
+            futures.append(&mut inner_futures);
+            futures.push(Box::pin(
+                execute_assign_watermarks(catalog, watermark, inner_receiver, sender).map(Ok),
+            ));
         }
         planners::QueryPlan::ValuesScan(values) => {
             let context = Record::new();
@@ -131,7 +488,7 @@ async fn execute_plan<'a>(
                     )
                 }))
             }) {
-                sender.send(record).await.unwrap();
+                sender.send(Message::Record(record)).await.unwrap();
             }
         }
     }
@@ -139,13 +496,18 @@ async fn execute_plan<'a>(
     futures
 }
 
-async fn execute_projection<'a>(
-    catalog: &'a Catalog,
+async fn execute_projection(
+    catalog: &Catalog,
     items: HashMap<String, Expr>,
-    mut receiver: mpsc::Receiver<Record>,
-    sender: mpsc::Sender<Record>,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
 ) {
-    while let Some(input_record) = receiver.recv().await {
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(input_record) = message else {
+            sender.send(message).await.unwrap();
+            continue;
+        };
+
         let mut output_record = Record::new();
 
         for (key, expr) in items.iter() {
@@ -153,97 +515,174 @@ async fn execute_projection<'a>(
             output_record.insert(key.clone(), value);
         }
 
-        sender.send(output_record).await.unwrap();
+        sender.send(Message::Record(output_record)).await.unwrap();
     }
 }
 
-async fn execute_full_join<'a>(
-    mut left_receiver: mpsc::Receiver<Record>,
-    mut right_receiver: mpsc::Receiver<Record>,
-    sender: mpsc::Sender<Record>,
+/// Cross join of both inputs. Each input is consumed as soon as it has records, so a quiet
+/// input does not hold back the other, and the join runs until both inputs have ended.
+async fn execute_full_join(
+    mut left_receiver: mpsc::Receiver<Message>,
+    mut right_receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
 ) {
     let mut left_buffer: Vec<Record> = Vec::new();
     let mut right_buffer: Vec<Record> = Vec::new();
 
+    let mut watermarks = JoinWatermarks::default();
+
+    let (mut left_open, mut right_open) = (true, true);
+
     loop {
-        if let Some(left_record) = &left_receiver.recv().await {
-            left_buffer.push(left_record.clone());
+        tokio::select! {
+            message = left_receiver.recv(), if left_open => match message {
+                Some(Message::Record(left_record)) => {
+                    for right_record in right_buffer.iter() {
+                        let mut record = left_record.clone();
+                        record.extend(right_record.clone());
+                        sender.send(Message::Record(record)).await.unwrap();
+                    }
 
-            for right_record in right_buffer.iter() {
-                let mut record = left_record.clone();
-                record.extend(right_record.clone());
-                sender.send(record).await.unwrap();
-            }
-        } else {
-            break;
-        }
+                    // Once the other input has ended no record can pair with this one later.
+                    if right_open {
+                        left_buffer.push(left_record);
+                    }
+                }
+                Some(Message::Watermark(watermark)) => {
+                    if let Some(watermark) = watermarks.advance_left(watermark) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+                }
+                None => {
+                    left_open = false;
+                    right_buffer.clear();
 
-        if let Some(right_record) = &right_receiver.recv().await {
-            right_buffer.push(right_record.clone());
+                    // An ended input no longer holds back the watermark of the other one.
+                    if let Some(watermark) = watermarks.advance_left(i64::MAX) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+                }
+            },
+            message = right_receiver.recv(), if right_open => match message {
+                Some(Message::Record(right_record)) => {
+                    for left_record in left_buffer.iter() {
+                        let mut record = left_record.clone();
+                        record.extend(right_record.clone());
+                        sender.send(Message::Record(record)).await.unwrap();
+                    }
 
-            for left_record in left_buffer.iter() {
-                let mut record = left_record.clone();
-                record.extend(right_record.clone());
-                sender.send(record).await.unwrap();
-            }
-        } else {
-            break;
+                    if left_open {
+                        right_buffer.push(right_record);
+                    }
+                }
+                Some(Message::Watermark(watermark)) => {
+                    if let Some(watermark) = watermarks.advance_right(watermark) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+                }
+                None => {
+                    right_open = false;
+                    left_buffer.clear();
+
+                    if let Some(watermark) = watermarks.advance_right(i64::MAX) {
+                        sender.send(Message::Watermark(watermark)).await.unwrap();
+                    }
+                }
+            },
+            else => break,
         }
+    }
+}
 
+async fn execute_qualify(
+    name: String,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+) {
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(mut record) = message else {
+            sender.send(message).await.unwrap();
+            continue;
+        };
+
+        let qualified_columns = record
+            .iter()
+            .filter(|(key, _)| !key.contains('.'))
+            .map(|(key, value)| (format!("{name}.{key}"), value.clone()))
+            .collect::<Vec<_>>();
+
+        record.extend(qualified_columns);
+
+        sender.send(Message::Record(record)).await.unwrap();
     }
 }
 
-async fn execute_filter<'a>(
-    catalog: &'a Catalog,
+async fn execute_filter(
+    catalog: &Catalog,
     expr: Expr,
-    mut receiver: mpsc::Receiver<Record>,
-    sender: mpsc::Sender<Record>,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
 ) {
-    while let Some(record) = receiver.recv().await {
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(record) = message else {
+            sender.send(message).await.unwrap();
+            continue;
+        };
+
         if let serde_json::Value::Bool(b) = evaluate_expr(catalog, &record, &expr) {
             if b {
-                sender.send(record).await.unwrap();
+                sender.send(Message::Record(record)).await.unwrap();
             }
         }
     }
 }
 
-fn evaluate_select_items<'a>(
-    catalog: &'a Catalog,
-    input: &Record,
-    select_items: &Vec<SelectItem>,
-) -> Record {
-    let mut output = serde_json::Map::new();
-
-    for (index, select_item) in select_items.iter().enumerate() {
-        match select_item {
-            SelectItem::NamedExpr(expr, alias) => {
-                output.insert(alias.to_string(), evaluate_expr(catalog, &input, expr));
-            }
-            SelectItem::Expr(expr) => match expr {
-                Expr::Ident(ident) => {
-                    output.insert(ident.to_string(), evaluate_expr(catalog, &input, expr));
-                }
-                Expr::FunctionCall(function_name, _) => {
-                    output.insert(
-                        function_name.to_string(),
-                        evaluate_expr(catalog, &input, expr),
-                    );
-                }
-                _ => {
-                    output.insert(
-                        format!("column{index}").to_string(),
-                        evaluate_expr(catalog, input, expr),
+/// Forwards records and emits a watermark whenever the watermark expression, evaluated on a
+/// record, moves past the previous watermark.
+async fn execute_assign_watermarks(
+    catalog: &Catalog,
+    watermark: Watermark,
+    mut receiver: mpsc::Receiver<Message>,
+    sender: mpsc::Sender<Message>,
+) {
+    let mut current_watermark = i64::MIN;
+
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(record) = message else {
+            sender.send(message).await.unwrap();
+            continue;
+        };
+
+        let next_watermark =
+            record
+                .get(&watermark.column)
+                .and_then(event_time)
+                .and_then(|record_time| {
+                    // The watermark expression does its arithmetic on the event time column in
+                    // milliseconds, whichever way the column itself is represented.
+                    let mut context = record.clone();
+                    context.insert(
+                        watermark.column.clone(),
+                        serde_json::Value::from(record_time),
                     );
-                }
-            },
+                    event_time(&evaluate_expr(catalog, &context, &watermark.expr))
+                });
+
+        sender.send(Message::Record(record)).await.unwrap();
+
+        if let Some(next_watermark) = next_watermark {
+            if next_watermark > current_watermark {
+                current_watermark = next_watermark;
+                sender
+                    .send(Message::Watermark(current_watermark))
+                    .await
+                    .unwrap();
+            }
         }
     }
-
-    output
 }
 
-fn evaluate_expr<'a>(catalog: &'a Catalog, record: &Record, expr: &Expr) -> serde_json::Value {
+fn evaluate_expr(catalog: &Catalog, record: &Record, expr: &Expr) -> serde_json::Value {
     match expr {
         Expr::FunctionCall(function_name, function_call_exprs) => {
             match catalog.functions.get(function_name) {
@@ -283,6 +722,25 @@ fn evaluate_expr<'a>(catalog: &'a Catalog, record: &Record, expr: &Expr) -> serd
             let right = evaluate_expr(catalog, record, right_expr);
 
             match (left, binary_operator, right) {
+                (left, BinaryOperator::And, right) => match (left.as_bool(), right.as_bool()) {
+                    (Some(false), _) | (_, Some(false)) => serde_json::Value::Bool(false),
+                    (Some(true), Some(true)) => serde_json::Value::Bool(true),
+                    _ => serde_json::Value::Null,
+                },
+                (left, BinaryOperator::Or, right) => match (left.as_bool(), right.as_bool()) {
+                    (Some(true), _) | (_, Some(true)) => serde_json::Value::Bool(true),
+                    (Some(false), Some(false)) => serde_json::Value::Bool(false),
+                    _ => serde_json::Value::Null,
+                },
+                // Whether NULL equals anything is unknown, so that NOT (a = b) does not hold
+                // either when a is NULL.
+                (serde_json::Value::Null, BinaryOperator::Eq, _)
+                | (_, BinaryOperator::Eq, serde_json::Value::Null) => serde_json::Value::Null,
+                (
+                    serde_json::Value::Number(left),
+                    BinaryOperator::Eq,
+                    serde_json::Value::Number(right),
+                ) => serde_json::Value::from(left.as_f64().unwrap() == right.as_f64().unwrap()),
                 (left, BinaryOperator::Eq, right) => serde_json::Value::from(left == right),
                 (
                     serde_json::Value::Number(left),
@@ -327,5 +785,56 @@ fn evaluate_expr<'a>(catalog: &'a Catalog, record: &Record, expr: &Expr) -> serd
                 _ => serde_json::Value::Null,
             }
         }
+        Expr::Interval(quantity, unit) => unit
+            .interval_milliseconds(*quantity)
+            .map_or(serde_json::Value::Null, serde_json::Value::from),
+        Expr::UnaryOperation(unary_operator, expr) => {
+            let value = evaluate_expr(catalog, record, expr);
+
+            match (unary_operator, value) {
+                (UnaryOperator::Not, serde_json::Value::Bool(b)) => serde_json::Value::Bool(!b),
+                _ => serde_json::Value::Null,
+            }
+        }
     }
 }
+
+/// A value as a CSV field or table cell: strings without quotes, NULL as nothing and everything
+/// else as JSON.
+fn format_field(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(string) => string.clone(),
+        serde_json::Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Event time in milliseconds since the Unix epoch, from either a number of milliseconds or an
+/// RFC 3339 timestamp string.
+fn event_time(value: &serde_json::Value) -> Option<i64> {
+    match value {
+        serde_json::Value::Number(number) => number
+            .as_i64()
+            .or_else(|| number.as_f64().map(|number| number as i64)),
+        serde_json::Value::String(string) => chrono::DateTime::parse_from_rfc3339(string)
+            .ok()
+            .map(|date_time| date_time.timestamp_millis()),
+        _ => None,
+    }
+}
+
+fn format_event_time(event_time: i64) -> serde_json::Value {
+    match chrono::DateTime::from_timestamp_millis(event_time) {
+        Some(date_time) => serde_json::Value::String(
+            date_time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        ),
+        None => serde_json::Value::Null,
+    }
+}
+
+/// Runs blocking file or network calls on the blocking thread pool, passing on their panics.
+async fn run_blocking<T: Send + 'static>(function: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(function)
+        .await
+        .unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+}
@@ -0,0 +1,86 @@
+use tokio::sync::mpsc;
+
+use crate::definitions::{Message, OutputFormat};
+
+use super::format_field;
+
+/// Prints the records of a SELECT to stdout. CSV and TABLE print the columns of the first
+/// record, in a header line and for every record after it.
+pub async fn execute_print(output_format: OutputFormat, mut receiver: mpsc::Receiver<Message>) {
+    // The columns with their width in TABLE.
+    let mut columns: Option<Vec<(String, usize)>> = None;
+    let mut csv_writer = csv::Writer::from_writer(std::io::stdout());
+
+    while let Some(message) = receiver.recv().await {
+        let Message::Record(record) = message else {
+            continue;
+        };
+
+        match output_format {
+            OutputFormat::Json => println!("{}", serde_json::to_string(&record).unwrap()),
+            OutputFormat::PrettyJson => {
+                println!("{}", serde_json::to_string_pretty(&record).unwrap())
+            }
+            OutputFormat::Csv => {
+                let columns = columns.get_or_insert_with(|| {
+                    let columns: Vec<(String, usize)> =
+                        record.keys().map(|column| (column.clone(), 0)).collect();
+                    csv_writer
+                        .write_record(columns.iter().map(|(column, _)| column))
+                        .unwrap();
+                    columns
+                });
+
+                csv_writer
+                    .write_record(columns.iter().map(|(column, _)| {
+                        record.get(column).map(format_field).unwrap_or_default()
+                    }))
+                    .unwrap();
+                csv_writer.flush().unwrap();
+            }
+            OutputFormat::Table => {
+                let columns = columns.get_or_insert_with(|| {
+                    let columns: Vec<(String, usize)> = record
+                        .iter()
+                        .map(|(column, value)| {
+                            let width = column.chars().count();
+                            (
+                                column.clone(),
+                                width.max(format_field(value).chars().count()),
+                            )
+                        })
+                        .collect();
+
+                    print_table_row(
+                        columns
+                            .iter()
+                            .map(|(column, width)| (column.clone(), *width)),
+                    );
+                    print_table_row(
+                        columns
+                            .iter()
+                            .map(|(_, width)| ("-".repeat(*width), *width)),
+                    );
+
+                    columns
+                });
+
+                // Wider values than those of the first record push the columns after them.
+                print_table_row(columns.iter().map(|(column, width)| {
+                    (
+                        record.get(column).map(format_field).unwrap_or_default(),
+                        *width,
+                    )
+                }));
+            }
+        }
+    }
+}
+
+fn print_table_row(cells: impl Iterator<Item = (String, usize)>) {
+    let row: Vec<String> = cells
+        .map(|(cell, width)| format!("{cell:width$}"))
+        .collect();
+
+    println!("{}", row.join(" | ").trim_end());
+}
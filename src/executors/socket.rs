@@ -0,0 +1,216 @@
+use std::net::SocketAddr;
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc,
+};
+
+use crate::{
+    definitions::{self, Catalog, Message, Record},
+    sql::{ErrorPolicy, Format, SocketProtocol, Watermark},
+};
+
+use super::{
+    dead_letters::{check_error_policy, ErrorHandler, PayloadDecoder},
+    decoders::{check_line_format, check_record_format, new_decoder},
+    file::LINE_NUMBER,
+};
+
+/// Column naming the client a payload of a dead letter of a socket or HTTP source was received
+/// from.
+pub const REMOTE_ADDRESS: &str = "_remote_address";
+
+/// Large enough for any UDP datagram.
+const MAX_DATAGRAM_BYTES: usize = 65_536;
+
+/// The longest line read from a TCP connection or HTTP body, as large as the data of a Kinesis
+/// record can be. A client sending a longer line is disconnected, so that it cannot make the
+/// line buffer grow without bound.
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+pub async fn execute_read_socket(
+    protocol: SocketProtocol,
+    address: String,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    match protocol {
+        SocketProtocol::Tcp => execute_read_tcp(address, format, error_handler, sender).await,
+        SocketProtocol::Udp => execute_read_udp(address, format, error_handler, sender).await,
+    }
+}
+
+/// Reads the lines sent over every connection accepted. A connection is only read from while
+/// the channel accepts its records, so TCP flow control holds back clients sending faster than
+/// the query keeps up with.
+async fn execute_read_tcp(
+    address: String,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|error| format!("could not listen on {address}: {error}"))?;
+
+    let mut connections = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, remote_address)) => {
+                    // Every connection gets its own decoder, so that a CSV header is read from
+                    // each.
+                    let decoder = PayloadDecoder::new(
+                        new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+                        error_handler.clone(),
+                    );
+
+                    connections.push(read_tcp_connection(
+                        stream,
+                        remote_address,
+                        decoder,
+                        sender.clone(),
+                    ));
+                }
+                Err(error) => eprintln!("could not accept a connection on {address}: {error}"),
+            },
+            Some(result) = connections.next(), if !connections.is_empty() => result?,
+        }
+    }
+}
+
+async fn read_tcp_connection(
+    stream: TcpStream,
+    remote_address: SocketAddr,
+    mut decoder: PayloadDecoder,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+
+    loop {
+        let limit = MAX_LINE_BYTES as u64 + 1;
+
+        match (&mut reader).take(limit).read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => {
+                eprintln!("could not read from {remote_address}: {error}");
+                break;
+            }
+        }
+
+        if line.len() > MAX_LINE_BYTES && !line.ends_with(b"\n") {
+            eprintln!(
+                "dropped {remote_address}, which sent a line longer than {MAX_LINE_BYTES} bytes"
+            );
+            break;
+        }
+
+        line_number += 1;
+
+        let content = line.strip_suffix(b"\n").map_or(line.as_slice(), |content| {
+            content.strip_suffix(b"\r").unwrap_or(content)
+        });
+
+        if !content.trim_ascii().is_empty() {
+            let metadata = Record::from_iter([
+                (
+                    REMOTE_ADDRESS.to_string(),
+                    serde_json::Value::from(remote_address.to_string()),
+                ),
+                (
+                    LINE_NUMBER.to_string(),
+                    serde_json::Value::from(line_number),
+                ),
+            ]);
+
+            for record in decoder.decode(content, &metadata).await? {
+                sender.send(Message::Record(record)).await.unwrap();
+            }
+        }
+
+        line.clear();
+    }
+
+    Ok(())
+}
+
+/// Reads every datagram received as a payload. UDP has no flow control, so datagrams arriving
+/// while the channel is full wait in the receive buffer of the socket, and are dropped once it
+/// is full.
+async fn execute_read_udp(
+    address: String,
+    format: Format,
+    error_handler: ErrorHandler,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let socket = UdpSocket::bind(&address)
+        .await
+        .map_err(|error| format!("could not listen on {address}: {error}"))?;
+
+    let mut decoder = PayloadDecoder::new(
+        new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+        error_handler,
+    );
+    let mut datagram = vec![0; MAX_DATAGRAM_BYTES];
+
+    loop {
+        let (length, remote_address) = match socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(error) => {
+                eprintln!("could not receive on {address}: {error}");
+                continue;
+            }
+        };
+
+        let metadata = Record::from_iter([(
+            REMOTE_ADDRESS.to_string(),
+            serde_json::Value::from(remote_address.to_string()),
+        )]);
+
+        for record in decoder.decode(&datagram[..length], &metadata).await? {
+            sender.send(Message::Record(record)).await.unwrap();
+        }
+    }
+}
+
+pub fn execute_create_socket_source(
+    catalog: &mut Catalog,
+    relation_ident: String,
+    protocol: SocketProtocol,
+    address: String,
+    format: Format,
+    error_policy: ErrorPolicy,
+    watermark: Option<Watermark>,
+) {
+    // UDP datagrams from every client are read by one decoder.
+    let checked = match protocol {
+        SocketProtocol::Tcp => check_line_format(&format),
+        SocketProtocol::Udp => check_record_format(&format),
+    };
+
+    if let Err(error) = checked.and_then(|_| new_decoder(&format)) {
+        panic!("{error}");
+    }
+
+    check_error_policy(catalog, &error_policy).unwrap_or_else(|error| panic!("{error}"));
+
+    let socket_source = definitions::SocketSource {
+        protocol,
+        address,
+        format,
+        error_policy,
+        watermark,
+    };
+
+    catalog.relations.insert(
+        relation_ident,
+        definitions::RelationDefinition::SocketSource(socket_source),
+    );
+}
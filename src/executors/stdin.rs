@@ -0,0 +1,53 @@
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+};
+
+use crate::{
+    definitions::{Message, Record},
+    sql::Format,
+};
+
+use super::{
+    dead_letters::{ErrorHandler, PayloadDecoder},
+    decoders::new_decoder,
+    file::LINE_NUMBER,
+};
+
+/// Reads a record from every line of standard input until it is closed. Lines that cannot be
+/// decoded are skipped.
+pub async fn execute_read_stdin(
+    format: Format,
+    sender: mpsc::Sender<Message>,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(tokio::io::stdin());
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    let mut decoder = PayloadDecoder::new(
+        new_decoder(&format).unwrap_or_else(|error| panic!("{error}")),
+        ErrorHandler::Skip,
+    );
+
+    while reader.read_until(b'\n', &mut line).await.unwrap() > 0 {
+        line_number += 1;
+
+        let content = line.strip_suffix(b"\n").map_or(line.as_slice(), |content| {
+            content.strip_suffix(b"\r").unwrap_or(content)
+        });
+
+        if !content.trim_ascii().is_empty() {
+            let metadata = Record::from_iter([(
+                LINE_NUMBER.to_string(),
+                serde_json::Value::from(line_number),
+            )]);
+
+            for record in decoder.decode(content, &metadata).await? {
+                sender.send(Message::Record(record)).await.unwrap();
+            }
+        }
+
+        line.clear();
+    }
+
+    Ok(())
+}
@@ -2,8 +2,6 @@ use std::collections::HashMap;
 
 use nom_locate::LocatedSpan;
 use nom_recursive::RecursiveInfo;
-use rusoto_core::Region;
-use rusoto_kinesis::KinesisClient;
 
 mod definitions;
 mod executors;
@@ -15,8 +13,13 @@ async fn main() {
     let catalog = &mut definitions::Catalog {
         relations: HashMap::new(),
         functions: HashMap::new(),
+        output_format: definitions::OutputFormat::default(),
     };
 
+    catalog
+        .relations
+        .insert("stdin".to_string(), definitions::RelationDefinition::Stdin);
+
     catalog.functions.insert(
         "lower".to_string(),
         definitions::FunctionDefinition::NativeFunction(|args| {
@@ -40,11 +43,28 @@ async fn main() {
 
     let input = arguments.pop().unwrap();
 
+    // The statements may be preceded by `--output-format format`.
+    if let Some(index) = arguments
+        .iter()
+        .position(|argument| argument == "--output-format")
+    {
+        let output_format = arguments
+            .get(index + 1)
+            .expect("--output-format needs to be followed by a format");
+
+        catalog.output_format = definitions::OutputFormat::from_name(output_format)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
     let (_, statements) =
         sql::parse_statements(LocatedSpan::new_extra(input.as_str(), RecursiveInfo::new()))
             .expect("could not parse statements");
 
+    // A failed statement ends the process, so that the statements after it do not run.
     for statement in statements {
-        executors::execute_statement(catalog, statement).await;
+        if let Err(error) = executors::execute_statement(catalog, statement).await {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
     }
 }
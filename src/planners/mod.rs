@@ -1,8 +1,16 @@
 use std::collections::HashMap;
 
+use rusoto_core::{credential::StaticProvider, Region};
+
 use crate::{
-    definitions::{Catalog, RelationDefinition},
-    sql::{Expr, FromItem, Query, SelectItem},
+    definitions::{
+        Catalog, FieldGenerator, KinesisReadMode, RelationDefinition, StartingOffsets,
+        StartingPosition,
+    },
+    sql::{
+        BinaryOperator, Encoding, ErrorPolicy, Expr, Format, FromItem, JoinType, Options, Query,
+        SelectItem, SocketProtocol, Watermark,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -17,26 +25,148 @@ pub enum QueryPlan {
     },
     KinesisStreamScan {
         kinesis_stream_name: String,
-        kinesis_stream_consumer_arn: String,
+        read_mode: KinesisReadMode,
+        format: Format,
+        error_policy: ErrorPolicy,
+        region: Region,
+        credentials: Option<StaticProvider>,
+        starting_position: StartingPosition,
+    },
+    KafkaTopicScan {
+        topic: String,
+        bootstrap_servers: String,
+        group_id: Option<String>,
+        partitions: Option<Vec<i32>>,
+        starting_offsets: StartingOffsets,
+        format: Format,
+        error_policy: ErrorPolicy,
+    },
+    FileScan {
+        path: String,
+        format: Format,
+        error_policy: ErrorPolicy,
+        tail: bool,
+    },
+    SocketScan {
+        protocol: SocketProtocol,
+        address: String,
+        format: Format,
+        error_policy: ErrorPolicy,
+    },
+    HttpScan {
+        address: String,
+        format: Format,
+        error_policy: ErrorPolicy,
+    },
+    GeneratorScan {
+        fields: Vec<(String, FieldGenerator)>,
+        rate: Option<u64>,
+        rows: Option<u64>,
+    },
+    StdinScan {
+        format: Format,
+    },
+    Aggregate {
+        group_by: HashMap<String, Expr>,
+        aggregates: HashMap<String, AggregateCall>,
+        query: Box<QueryPlan>,
+    },
+    WindowAggregate {
+        window: Window,
+        time: Expr,
+        group_by: HashMap<String, Expr>,
+        aggregates: HashMap<String, AggregateCall>,
+        query: Box<QueryPlan>,
+    },
+    AssignWatermarks {
+        watermark: Watermark,
+        query: Box<QueryPlan>,
     },
     ValuesScan(Vec<Vec<Expr>>),
     FullJoin(Box<QueryPlan>, Box<QueryPlan>),
+    /// Equi-join on `left_keys` and `right_keys`, with any other conditions of the ON clause in
+    /// `condition`. With `time_bounds` it is an interval join whose state is evicted as the
+    /// watermarks of its inputs advance.
+    Join {
+        join_type: JoinType,
+        left_keys: Vec<Expr>,
+        right_keys: Vec<Expr>,
+        time_bounds: Option<TimeBounds>,
+        condition: Option<Expr>,
+        left: Box<QueryPlan>,
+        right: Box<QueryPlan>,
+    },
+    /// Adds a copy of every column prefixed with the relation name, as in `clicks.user_id`.
+    Qualify {
+        name: String,
+        query: Box<QueryPlan>,
+    },
     Empty,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateCall {
+    pub function: AggregateFunction,
+    pub args: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFunction {
+    pub fn from_name(name: &str) -> Option<AggregateFunction> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(AggregateFunction::Count),
+            "sum" => Some(AggregateFunction::Sum),
+            "min" => Some(AggregateFunction::Min),
+            "max" => Some(AggregateFunction::Max),
+            "avg" => Some(AggregateFunction::Avg),
+            _ => None,
+        }
+    }
+}
+
+/// Bounds of an interval join: records only match if `right_time - left_time` is between
+/// `lower` and `upper` milliseconds, inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeBounds {
+    pub left_time: Expr,
+    pub right_time: Expr,
+    pub lower: i64,
+    pub upper: i64,
+}
+
+/// Window sizes are in milliseconds of event time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Tumbling { size: u64 },
+    Hopping { slide: u64, size: u64 },
+    Session { gap: u64 },
+}
+
+/// Columns added to the output of a windowed aggregation.
+pub const WINDOW_START: &str = "window_start";
+pub const WINDOW_END: &str = "window_end";
+
 pub fn plan_query(catalog: &Catalog, query: &Query) -> Result<QueryPlan, String> {
-    let mut plan = if query.from_items.len() > 0 {
+    let mut plan = if !query.from_items.is_empty() {
         let from_items = query.from_items.clone();
 
-        from_items[1..].iter().fold(
-            plan_from_item(catalog, from_items.first().unwrap()).expect("could not plan from item"),
+        from_items[1..].iter().try_fold(
+            plan_from_item(catalog, from_items.first().unwrap())?,
             |acc, from_item| {
-                QueryPlan::FullJoin(
+                Ok::<_, String>(QueryPlan::FullJoin(
                     Box::new(acc),
-                    Box::new(plan_from_item(catalog, from_item).expect("could not plan from item")),
-                )
+                    Box::new(plan_from_item(catalog, from_item)?),
+                ))
             },
-        )
+        )?
     } else {
         QueryPlan::Empty
     };
@@ -45,7 +175,54 @@ pub fn plan_query(catalog: &Catalog, query: &Query) -> Result<QueryPlan, String>
         plan = QueryPlan::Selection { condition: condition.clone(), query: Box::new(plan) };
     }
 
-    if query.select_items.len() > 0 {
+    let is_aggregation = !query.group_by.is_empty()
+        || query
+            .select_items
+            .iter()
+            .any(|select_item| match select_item {
+                SelectItem::Expr(expr) | SelectItem::NamedExpr(expr, _) => contains_aggregate(expr),
+            });
+
+    // Group keys and aggregate calls are computed by the aggregate node under generated
+    // names, and the select items are rewritten to refer to those names.
+    let mut group_keys = Vec::new();
+    let mut aggregate_calls = Vec::new();
+    let mut window = None;
+    // Names that select items may refer to in addition to the group keys.
+    let mut window_keys = Vec::new();
+
+    if is_aggregation {
+        for (i, expr) in query.group_by.iter().enumerate() {
+            if contains_aggregate(expr) {
+                return Err("aggregate functions are not allowed in GROUP BY".to_string());
+            }
+
+            if let Some((window_definition, time)) = plan_window(expr)? {
+                if window.is_some() {
+                    return Err("only one window function is allowed in GROUP BY".to_string());
+                }
+
+                window = Some((window_definition, time));
+                window_keys = vec![
+                    (WINDOW_START.to_string(), expr.clone()),
+                    (
+                        WINDOW_START.to_string(),
+                        Expr::Ident(WINDOW_START.to_string()),
+                    ),
+                    (WINDOW_END.to_string(), Expr::Ident(WINDOW_END.to_string())),
+                ];
+                continue;
+            }
+
+            let key = match expr {
+                Expr::Ident(ident) => ident.clone(),
+                _ => format!("group{i}"),
+            };
+            group_keys.push((key, expr.clone()));
+        }
+    }
+
+    if !query.select_items.is_empty() {
         let mut items = HashMap::new();
 
         for (i, select_item) in query.select_items.iter().enumerate() {
@@ -61,11 +238,37 @@ pub fn plan_query(catalog: &Catalog, query: &Query) -> Result<QueryPlan, String>
                 SelectItem::NamedExpr(expr, name) => (name.clone(), expr),
             };
 
-            if items.insert(key.clone(), value.clone()) != None {
+            let value = if is_aggregation {
+                rewrite_aggregate_expr(
+                    value,
+                    &[group_keys.as_slice(), window_keys.as_slice()].concat(),
+                    &mut aggregate_calls,
+                )?
+            } else {
+                value.clone()
+            };
+
+            if items.insert(key.clone(), value).is_some() {
                 return Err(format!("{key} already defined in select items"));
             }
         }
 
+        if let Some((window, time)) = window {
+            plan = QueryPlan::WindowAggregate {
+                window,
+                time,
+                group_by: HashMap::from_iter(group_keys),
+                aggregates: HashMap::from_iter(aggregate_calls),
+                query: Box::new(plan),
+            };
+        } else if is_aggregation {
+            plan = QueryPlan::Aggregate {
+                group_by: HashMap::from_iter(group_keys),
+                aggregates: HashMap::from_iter(aggregate_calls),
+                query: Box::new(plan),
+            };
+        }
+
         plan = QueryPlan::Projection {
             items,
             query: Box::new(plan),
@@ -77,15 +280,106 @@ pub fn plan_query(catalog: &Catalog, query: &Query) -> Result<QueryPlan, String>
 
 fn plan_from_item(catalog: &Catalog, from_item: &FromItem) -> Result<QueryPlan, String> {
     match from_item {
-        FromItem::Ident(ident) => {
+        FromItem::Ident(ident, options) => {
             if let Some(relation_definition) = catalog.relations.get(ident) {
                 match relation_definition {
                     RelationDefinition::KinesisStream(kinesis_stream) => {
-                        Ok(QueryPlan::KinesisStreamScan {
-                            kinesis_stream_name: kinesis_stream.kinesis_stream_name.clone(),
-                            kinesis_stream_consumer_arn: kinesis_stream.kinesis_stream_consumer_arn.clone(),
-                        })
+                        // Options in the query override those of the relation.
+                        let mut starting_position = kinesis_stream.starting_position.clone();
+
+                        for (key, value) in options {
+                            starting_position = StartingPosition::from_option(key, value)
+                                .ok_or_else(|| {
+                                    format!("unrecognized option {key} for {ident}")
+                                })??;
+                        }
+
+                        Ok(with_watermarks(
+                            QueryPlan::KinesisStreamScan {
+                                kinesis_stream_name: kinesis_stream.kinesis_stream_name.clone(),
+                                read_mode: kinesis_stream.read_mode.clone(),
+                                format: kinesis_stream.format.clone(),
+                                error_policy: kinesis_stream.error_policy.clone(),
+                                region: kinesis_stream.region.clone(),
+                                credentials: kinesis_stream.credentials.clone(),
+                                starting_position,
+                            },
+                            &kinesis_stream.watermark,
+                        ))
                     }
+                    RelationDefinition::KafkaTopic(kafka_topic) => {
+                        let mut starting_offsets = kafka_topic.starting_offsets.clone();
+
+                        for (key, value) in options {
+                            starting_offsets = StartingOffsets::from_option(key, value)
+                                .ok_or_else(|| {
+                                    format!("unrecognized option {key} for {ident}")
+                                })??;
+                        }
+
+                        Ok(with_watermarks(
+                            QueryPlan::KafkaTopicScan {
+                                topic: kafka_topic.topic.clone(),
+                                bootstrap_servers: kafka_topic.bootstrap_servers.clone(),
+                                group_id: kafka_topic.group_id.clone(),
+                                partitions: kafka_topic.partitions.clone(),
+                                starting_offsets,
+                                format: kafka_topic.format.clone(),
+                                error_policy: kafka_topic.error_policy.clone(),
+                            },
+                            &kafka_topic.watermark,
+                        ))
+                    }
+                    RelationDefinition::FileSink(_) => {
+                        Err(format!("{ident} is a sink and cannot be read from"))
+                    }
+                    RelationDefinition::FileSource(_) if !options.is_empty() => {
+                        Err(format!("{ident} does not take any options"))
+                    }
+                    RelationDefinition::FileSource(file_source) => Ok(with_watermarks(
+                        QueryPlan::FileScan {
+                            path: file_source.path.clone(),
+                            format: file_source.format.clone(),
+                            error_policy: file_source.error_policy.clone(),
+                            tail: file_source.tail,
+                        },
+                        &file_source.watermark,
+                    )),
+                    RelationDefinition::SocketSource(_)
+                    | RelationDefinition::HttpSource(_)
+                    | RelationDefinition::GeneratorSource(_)
+                        if !options.is_empty() =>
+                    {
+                        Err(format!("{ident} does not take any options"))
+                    }
+                    RelationDefinition::SocketSource(socket_source) => Ok(with_watermarks(
+                        QueryPlan::SocketScan {
+                            protocol: socket_source.protocol,
+                            address: socket_source.address.clone(),
+                            format: socket_source.format.clone(),
+                            error_policy: socket_source.error_policy.clone(),
+                        },
+                        &socket_source.watermark,
+                    )),
+                    RelationDefinition::HttpSource(http_source) => Ok(with_watermarks(
+                        QueryPlan::HttpScan {
+                            address: http_source.address.clone(),
+                            format: http_source.format.clone(),
+                            error_policy: http_source.error_policy.clone(),
+                        },
+                        &http_source.watermark,
+                    )),
+                    RelationDefinition::GeneratorSource(generator_source) => Ok(with_watermarks(
+                        QueryPlan::GeneratorScan {
+                            fields: generator_source.fields.clone(),
+                            rate: generator_source.rate,
+                            rows: generator_source.rows,
+                        },
+                        &generator_source.watermark,
+                    )),
+                    RelationDefinition::Stdin => Ok(QueryPlan::StdinScan {
+                        format: plan_stdin_format(ident, options)?,
+                    }),
                 }
             } else {
                 Err(format!("unrecognized relation {ident}"))
@@ -93,5 +387,869 @@ fn plan_from_item(catalog: &Catalog, from_item: &FromItem) -> Result<QueryPlan,
         }
         FromItem::SubQuery(query) => plan_query(catalog, query),
         FromItem::Values(values) => Ok(QueryPlan::ValuesScan(values.clone())),
+        FromItem::Aliased(from_item, alias) => Ok(QueryPlan::Qualify {
+            name: alias.clone(),
+            query: Box::new(plan_from_item(catalog, from_item)?),
+        }),
+        FromItem::Join(left_from_item, join_type, right_from_item, condition) => {
+            let left_names = relation_names(left_from_item);
+            let right_names = relation_names(right_from_item);
+
+            let (left_keys, right_keys, condition) =
+                plan_join_condition(condition, &left_names, &right_names);
+            let (time_bounds, condition) = match condition {
+                Some(condition) => plan_time_bounds(&condition, &left_names, &right_names),
+                None => (None, None),
+            };
+
+            Ok(QueryPlan::Join {
+                join_type: *join_type,
+                left_keys,
+                right_keys,
+                time_bounds,
+                condition,
+                left: Box::new(plan_join_operand(catalog, left_from_item)?),
+                right: Box::new(plan_join_operand(catalog, right_from_item)?),
+            })
+        }
+    }
+}
+
+/// Reads the `format` option of `stdin`, which is read as JSON by default. stdin is read line
+/// by line, as a file source is, so only formats that decode a line of text can be given.
+fn plan_stdin_format(ident: &str, options: &Options) -> Result<Format, String> {
+    let mut format = Format::default();
+
+    for (key, value) in options {
+        match key.as_str() {
+            "format" => {
+                format.encoding = match value.to_uppercase().as_str() {
+                    "JSON" => Encoding::Json,
+                    "CSV" => Encoding::Csv(None),
+                    "TEXT" => Encoding::Text,
+                    _ => return Err(format!("format needs to be JSON, CSV or TEXT, not {value}")),
+                }
+            }
+            _ => return Err(format!("unrecognized option {key} for {ident}")),
+        }
+    }
+
+    Ok(format)
+}
+
+/// Relations joined by name get their columns qualified, so that the ON clause and the select
+/// items can tell apart columns of the same name.
+fn plan_join_operand(catalog: &Catalog, from_item: &FromItem) -> Result<QueryPlan, String> {
+    match from_item {
+        FromItem::Ident(ident, _) => Ok(QueryPlan::Qualify {
+            name: ident.clone(),
+            query: Box::new(plan_from_item(catalog, from_item)?),
+        }),
+        _ => plan_from_item(catalog, from_item),
+    }
+}
+
+fn relation_names(from_item: &FromItem) -> Vec<String> {
+    match from_item {
+        FromItem::Ident(ident, _) => vec![ident.clone()],
+        FromItem::Aliased(_, alias) => vec![alias.clone()],
+        FromItem::Join(left_from_item, _, right_from_item, _) => [
+            relation_names(left_from_item),
+            relation_names(right_from_item),
+        ]
+        .concat(),
+        FromItem::SubQuery(_) | FromItem::Values(_) => vec![],
+    }
+}
+
+/// Splits an ON clause into the key expressions of its equalities between the two sides and
+/// the remaining conditions. A side is recognized by the relation names of its columns, so
+/// only qualified columns can become join keys.
+fn plan_join_condition(
+    condition: &Expr,
+    left_names: &[String],
+    right_names: &[String],
+) -> (Vec<Expr>, Vec<Expr>, Option<Expr>) {
+    let mut left_keys = Vec::new();
+    let mut right_keys = Vec::new();
+    let mut remaining_condition = None;
+
+    for conjunct in split_conjunction(condition) {
+        if let Expr::BinaryOperation(left_expr, BinaryOperator::Eq, right_expr) = &conjunct {
+            let left_relations = referenced_relations(left_expr);
+            let right_relations = referenced_relations(right_expr);
+
+            let is_from = |relations: &Option<Vec<String>>, names: &[String]| match relations {
+                Some(relations) if !relations.is_empty() => {
+                    relations.iter().all(|relation| names.contains(relation))
+                }
+                _ => false,
+            };
+
+            if is_from(&left_relations, left_names) && is_from(&right_relations, right_names) {
+                left_keys.push(*left_expr.clone());
+                right_keys.push(*right_expr.clone());
+                continue;
+            }
+
+            if is_from(&left_relations, right_names) && is_from(&right_relations, left_names) {
+                left_keys.push(*right_expr.clone());
+                right_keys.push(*left_expr.clone());
+                continue;
+            }
+        }
+
+        remaining_condition = Some(match remaining_condition {
+            Some(remaining_condition) => Expr::BinaryOperation(
+                Box::new(remaining_condition),
+                BinaryOperator::And,
+                Box::new(conjunct),
+            ),
+            None => conjunct,
+        });
+    }
+
+    (left_keys, right_keys, remaining_condition)
+}
+
+/// Looks for comparisons between the times of both sides, such as
+/// `b.ts BETWEEN a.ts AND a.ts + INTERVAL '10' MINUTE`, that bound the difference of the two
+/// times from below and above. Returns the bounds, if any, and the rest of the condition.
+fn plan_time_bounds(
+    condition: &Expr,
+    left_names: &[String],
+    right_names: &[String],
+) -> (Option<TimeBounds>, Option<Expr>) {
+    let side_of = |expr: &Expr| match referenced_relations(expr) {
+        Some(relations) if !relations.is_empty() => {
+            if relations
+                .iter()
+                .all(|relation| left_names.contains(relation))
+            {
+                Some(true)
+            } else if relations
+                .iter()
+                .all(|relation| right_names.contains(relation))
+            {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    // Splits `time + INTERVAL ...` into the time and its offset in milliseconds.
+    let time_offset = |expr: &Expr| match expr {
+        Expr::BinaryOperation(time, BinaryOperator::Add, interval) => match **interval {
+            Expr::Interval(quantity, unit) => match unit.interval_milliseconds(quantity) {
+                Some(offset) => ((**time).clone(), offset),
+                None => (expr.clone(), 0),
+            },
+            _ => (expr.clone(), 0),
+        },
+        Expr::BinaryOperation(time, BinaryOperator::Sub, interval) => match **interval {
+            Expr::Interval(quantity, unit) => match unit.interval_milliseconds(quantity) {
+                Some(offset) => ((**time).clone(), -offset),
+                None => (expr.clone(), 0),
+            },
+            _ => (expr.clone(), 0),
+        },
+        _ => (expr.clone(), 0),
+    };
+
+    let mut left_time: Option<Expr> = None;
+    let mut right_time: Option<Expr> = None;
+    let mut lower = None;
+    let mut upper = None;
+    let mut remaining_conjuncts = Vec::new();
+
+    for conjunct in split_conjunction(condition) {
+        let bound = match &conjunct {
+            Expr::BinaryOperation(left_expr, binary_operator, right_expr) => {
+                let (left_expr_time, left_expr_offset) = time_offset(left_expr);
+                let (right_expr_time, right_expr_offset) = time_offset(right_expr);
+
+                // Rewrites the comparison as `right time - left time <op> difference`. A
+                // difference too large for an event time is left as a plain condition.
+                match (side_of(&left_expr_time), side_of(&right_expr_time)) {
+                    (Some(false), Some(true)) => right_expr_offset
+                        .checked_sub(left_expr_offset)
+                        .map(|difference| {
+                            (
+                                right_expr_time,
+                                left_expr_time,
+                                binary_operator.clone(),
+                                difference,
+                            )
+                        }),
+                    (Some(true), Some(false)) => left_expr_offset
+                        .checked_sub(right_expr_offset)
+                        .map(|difference| {
+                            (
+                                left_expr_time,
+                                right_expr_time,
+                                match binary_operator {
+                                    BinaryOperator::Gte => BinaryOperator::Lte,
+                                    BinaryOperator::Gt => BinaryOperator::Lt,
+                                    BinaryOperator::Lte => BinaryOperator::Gte,
+                                    BinaryOperator::Lt => BinaryOperator::Gt,
+                                    binary_operator => binary_operator.clone(),
+                                },
+                                difference,
+                            )
+                        }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let Some((conjunct_left_time, conjunct_right_time, binary_operator, difference)) = bound
+            .filter(|(conjunct_left_time, conjunct_right_time, _, _)| {
+                left_time
+                    .as_ref()
+                    .is_none_or(|time| time == conjunct_left_time)
+                    && right_time
+                        .as_ref()
+                        .is_none_or(|time| time == conjunct_right_time)
+            })
+        else {
+            remaining_conjuncts.push(conjunct);
+            continue;
+        };
+
+        // `>` and `<` become inclusive bounds, unless that would overflow.
+        let (is_lower, bound) = match binary_operator {
+            BinaryOperator::Gte => (true, Some(difference)),
+            BinaryOperator::Gt => (true, difference.checked_add(1)),
+            BinaryOperator::Lte => (false, Some(difference)),
+            BinaryOperator::Lt => (false, difference.checked_sub(1)),
+            _ => (false, None),
+        };
+
+        match (is_lower, bound) {
+            (true, Some(bound)) => lower = lower.max(Some(bound)),
+            (false, Some(bound)) => {
+                upper = Some(upper.map_or(bound, |upper: i64| upper.min(bound)))
+            }
+            (_, None) => {
+                remaining_conjuncts.push(conjunct);
+                continue;
+            }
+        }
+
+        left_time = Some(conjunct_left_time);
+        right_time = Some(conjunct_right_time);
+    }
+
+    let (Some(left_time), Some(right_time), Some(lower), Some(upper)) =
+        (left_time, right_time, lower, upper)
+    else {
+        // Without both bounds the state cannot be evicted, so plan a regular join.
+        return (None, Some(condition.clone()));
+    };
+
+    let remaining_condition = remaining_conjuncts.into_iter().reduce(|acc, conjunct| {
+        Expr::BinaryOperation(Box::new(acc), BinaryOperator::And, Box::new(conjunct))
+    });
+
+    (
+        Some(TimeBounds {
+            left_time,
+            right_time,
+            lower,
+            upper,
+        }),
+        remaining_condition,
+    )
+}
+
+fn split_conjunction(expr: &Expr) -> Vec<Expr> {
+    match expr {
+        Expr::BinaryOperation(left_expr, BinaryOperator::And, right_expr) => {
+            [split_conjunction(left_expr), split_conjunction(right_expr)].concat()
+        }
+        _ => vec![expr.clone()],
+    }
+}
+
+/// The relation names qualifying the columns of an expression, or `None` if it refers to an
+/// unqualified column.
+fn referenced_relations(expr: &Expr) -> Option<Vec<String>> {
+    match expr {
+        Expr::Ident(ident) => ident
+            .split_once('.')
+            .map(|(relation, _)| vec![relation.to_string()]),
+        Expr::FunctionCall(_, args) => args
+            .iter()
+            .map(referenced_relations)
+            .collect::<Option<Vec<_>>>()
+            .map(|relations| relations.concat()),
+        Expr::BinaryOperation(left_expr, _, right_expr) => Some(
+            [
+                referenced_relations(left_expr)?,
+                referenced_relations(right_expr)?,
+            ]
+            .concat(),
+        ),
+        Expr::UnaryOperation(_, expr) => referenced_relations(expr),
+        Expr::String(_) | Expr::Number(_) | Expr::Interval(_, _) => Some(vec![]),
+    }
+}
+
+fn contains_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::FunctionCall(name, args) => {
+            AggregateFunction::from_name(name).is_some() || args.iter().any(contains_aggregate)
+        }
+        Expr::BinaryOperation(left_expr, _, right_expr) => {
+            contains_aggregate(left_expr) || contains_aggregate(right_expr)
+        }
+        Expr::UnaryOperation(_, expr) => contains_aggregate(expr),
+        Expr::Ident(_) | Expr::String(_) | Expr::Number(_) | Expr::Interval(_, _) => false,
+    }
+}
+
+fn rewrite_aggregate_expr(
+    expr: &Expr,
+    group_keys: &[(String, Expr)],
+    aggregate_calls: &mut Vec<(String, AggregateCall)>,
+) -> Result<Expr, String> {
+    if let Some((key, _)) = group_keys.iter().find(|(_, group_expr)| group_expr == expr) {
+        return Ok(Expr::Ident(key.clone()));
+    }
+
+    match expr {
+        Expr::FunctionCall(name, args) => {
+            if let Some(function) = AggregateFunction::from_name(name) {
+                if args.iter().any(contains_aggregate) {
+                    return Err(format!(
+                        "aggregate function calls cannot be nested in {name}()"
+                    ));
+                }
+
+                if function != AggregateFunction::Count && args.len() != 1 {
+                    return Err(format!("{name}() needs to be called with 1 argument"));
+                }
+
+                let aggregate_call = AggregateCall {
+                    function,
+                    args: args.clone(),
+                };
+
+                let key = match aggregate_calls
+                    .iter()
+                    .find(|(_, existing_call)| *existing_call == aggregate_call)
+                {
+                    Some((key, _)) => key.clone(),
+                    None => {
+                        let key = format!("aggregate{}", aggregate_calls.len());
+                        aggregate_calls.push((key.clone(), aggregate_call));
+                        key
+                    }
+                };
+
+                Ok(Expr::Ident(key))
+            } else {
+                Ok(Expr::FunctionCall(
+                    name.clone(),
+                    args.iter()
+                        .map(|arg| rewrite_aggregate_expr(arg, group_keys, aggregate_calls))
+                        .collect::<Result<_, _>>()?,
+                ))
+            }
+        }
+        Expr::BinaryOperation(left_expr, binary_operator, right_expr) => Ok(Expr::BinaryOperation(
+            Box::new(rewrite_aggregate_expr(
+                left_expr,
+                group_keys,
+                aggregate_calls,
+            )?),
+            binary_operator.clone(),
+            Box::new(rewrite_aggregate_expr(
+                right_expr,
+                group_keys,
+                aggregate_calls,
+            )?),
+        )),
+        Expr::UnaryOperation(unary_operator, expr) => Ok(Expr::UnaryOperation(
+            unary_operator.clone(),
+            Box::new(rewrite_aggregate_expr(expr, group_keys, aggregate_calls)?),
+        )),
+        Expr::Ident(ident) => Err(format!(
+            "{ident} must appear in GROUP BY or be used in an aggregate function"
+        )),
+        Expr::String(_) | Expr::Number(_) | Expr::Interval(_, _) => Ok(expr.clone()),
+    }
+}
+
+fn with_watermarks(plan: QueryPlan, watermark: &Option<Watermark>) -> QueryPlan {
+    match watermark {
+        Some(watermark) => QueryPlan::AssignWatermarks {
+            watermark: watermark.clone(),
+            query: Box::new(plan),
+        },
+        None => plan,
+    }
+}
+
+/// Recognizes the window functions `TUMBLE(time, size)`, `HOP(time, slide, size)` and
+/// `SESSION(time, gap)` in GROUP BY, returning the window and the event time expression.
+fn plan_window(expr: &Expr) -> Result<Option<(Window, Expr)>, String> {
+    let Expr::FunctionCall(name, args) = expr else {
+        return Ok(None);
+    };
+
+    let interval = |expr: &Expr| match expr {
+        Expr::Interval(quantity, unit) if *quantity > 0 => unit
+            .interval_milliseconds(*quantity)
+            .map(|milliseconds| milliseconds as u64)
+            .ok_or_else(|| format!("the INTERVAL of {name}() is too long")),
+        _ => Err(format!("{name}() needs a positive INTERVAL literal")),
+    };
+
+    let window = match (name.to_lowercase().as_str(), args.as_slice()) {
+        ("tumble", [_, size]) => Window::Tumbling {
+            size: interval(size)?,
+        },
+        ("hop", [_, slide, size]) => Window::Hopping {
+            slide: interval(slide)?,
+            size: interval(size)?,
+        },
+        ("session", [_, gap]) => Window::Session {
+            gap: interval(gap)?,
+        },
+        ("tumble", _) => return Err(format!("{name}() needs to be called with 2 arguments")),
+        ("hop", _) => return Err(format!("{name}() needs to be called with 3 arguments")),
+        ("session", _) => return Err(format!("{name}() needs to be called with 2 arguments")),
+        _ => return Ok(None),
+    };
+
+    Ok(Some((window, args[0].clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use nom_locate::LocatedSpan;
+    use nom_recursive::RecursiveInfo;
+
+    use super::*;
+    use crate::{
+        definitions::OutputFormat,
+        sql::{parse_statement, IntervalUnit, Statement},
+    };
+
+    fn new_catalog() -> Catalog {
+        Catalog {
+            relations: HashMap::new(),
+            functions: HashMap::new(),
+            output_format: OutputFormat::default(),
+        }
+    }
+
+    fn parse_query(sql: &str) -> Query {
+        match parse_statement(LocatedSpan::new_extra(sql, RecursiveInfo::new())) {
+            Ok((_, Statement::Select(query))) => query,
+            result => panic!("could not parse {sql}: {result:?}"),
+        }
+    }
+
+    fn parse_expr(sql: &str) -> Expr {
+        match &parse_query(&format!("SELECT {sql} AS e FROM (VALUES (1)) v;")).select_items[..] {
+            [SelectItem::NamedExpr(expr, _)] => expr.clone(),
+            select_items => panic!("could not parse {sql}: {select_items:?}"),
+        }
+    }
+
+    fn plan(sql: &str) -> Result<QueryPlan, String> {
+        plan_query(&new_catalog(), &parse_query(sql))
+    }
+
+    fn ident(name: &str) -> Expr {
+        Expr::Ident(name.to_string())
+    }
+
+    fn aggregate_call(function: AggregateFunction, args: &[&str]) -> AggregateCall {
+        AggregateCall {
+            function,
+            args: args.iter().map(|arg| parse_expr(arg)).collect(),
+        }
+    }
+
+    /// Splits a planned aggregation into its select items and aggregate node.
+    fn split_aggregation(plan: QueryPlan) -> (HashMap<String, Expr>, QueryPlan) {
+        match plan {
+            QueryPlan::Projection { items, query } => (items, *query),
+            plan => panic!("expected a projection, not {plan:?}"),
+        }
+    }
+
+    #[test]
+    fn groups_by_columns_and_expressions() {
+        let (items, aggregate) = split_aggregation(
+            plan(
+                "SELECT user_id, lower(name) AS name, COUNT(id) AS clicks, \
+                 SUM(amount) + 1 AS total FROM (VALUES (1)) v GROUP BY user_id, lower(name);",
+            )
+            .unwrap(),
+        );
+
+        let QueryPlan::Aggregate {
+            group_by,
+            aggregates,
+            ..
+        } = aggregate
+        else {
+            panic!("expected an aggregate, not {aggregate:?}");
+        };
+
+        // Columns are grouped by under their own name, other expressions under a generated one.
+        assert_eq!(
+            group_by,
+            HashMap::from([
+                ("user_id".to_string(), ident("user_id")),
+                ("group1".to_string(), parse_expr("lower(name)")),
+            ])
+        );
+        assert_eq!(
+            aggregates,
+            HashMap::from([
+                (
+                    "aggregate0".to_string(),
+                    aggregate_call(AggregateFunction::Count, &["id"])
+                ),
+                (
+                    "aggregate1".to_string(),
+                    aggregate_call(AggregateFunction::Sum, &["amount"])
+                ),
+            ])
+        );
+
+        assert_eq!(items["user_id"], ident("user_id"));
+        assert_eq!(items["name"], ident("group1"));
+        assert_eq!(items["clicks"], ident("aggregate0"));
+        assert_eq!(items["total"], parse_expr("aggregate1 + 1"));
+    }
+
+    #[test]
+    fn computes_an_aggregate_call_once() {
+        let (items, aggregate) = split_aggregation(
+            plan(
+                "SELECT COUNT(id) AS clicks, COUNT(id) * 2 AS doubled, COUNT(*) AS rows \
+                 FROM (VALUES (1)) v;",
+            )
+            .unwrap(),
+        );
+
+        let QueryPlan::Aggregate {
+            group_by,
+            aggregates,
+            ..
+        } = aggregate
+        else {
+            panic!("expected an aggregate, not {aggregate:?}");
+        };
+
+        // Without GROUP BY every record falls into the same group.
+        assert!(group_by.is_empty());
+        assert_eq!(aggregates.len(), 2);
+        assert_eq!(
+            aggregates["aggregate1"],
+            aggregate_call(AggregateFunction::Count, &[])
+        );
+
+        assert_eq!(items["clicks"], ident("aggregate0"));
+        assert_eq!(items["doubled"], parse_expr("aggregate0 * 2"));
+        assert_eq!(items["rows"], ident("aggregate1"));
+    }
+
+    #[test]
+    fn extracts_the_window_function() {
+        let (items, aggregate) = split_aggregation(
+            plan(
+                "SELECT user_id, TUMBLE(ts, INTERVAL '1' MINUTE) AS window, window_end, \
+                 COUNT(id) AS clicks FROM (VALUES (1)) v \
+                 GROUP BY TUMBLE(ts, INTERVAL '1' MINUTE), user_id;",
+            )
+            .unwrap(),
+        );
+
+        let QueryPlan::WindowAggregate {
+            window,
+            time,
+            group_by,
+            aggregates,
+            ..
+        } = aggregate
+        else {
+            panic!("expected a window aggregate, not {aggregate:?}");
+        };
+
+        assert_eq!(window, Window::Tumbling { size: 60_000 });
+        assert_eq!(time, ident("ts"));
+        // The window function is not a grouping key of its own.
+        assert_eq!(
+            group_by,
+            HashMap::from([("user_id".to_string(), ident("user_id"))])
+        );
+        assert_eq!(aggregates.len(), 1);
+
+        assert_eq!(items["window"], ident(WINDOW_START));
+        assert_eq!(items["window_end"], ident(WINDOW_END));
+        assert_eq!(items["clicks"], ident("aggregate0"));
+    }
+
+    #[test]
+    fn plans_hopping_and_session_windows() {
+        assert_eq!(
+            plan_window(&parse_expr(
+                "HOP(ts, INTERVAL '10' SECOND, INTERVAL '1' MINUTE)"
+            )),
+            Ok(Some((
+                Window::Hopping {
+                    slide: 10_000,
+                    size: 60_000
+                },
+                ident("ts")
+            )))
+        );
+        assert_eq!(
+            plan_window(&parse_expr("session(ts, INTERVAL '5' MINUTE)")),
+            Ok(Some((Window::Session { gap: 300_000 }, ident("ts"))))
+        );
+        assert_eq!(plan_window(&parse_expr("lower(ts)")), Ok(None));
+    }
+
+    #[test]
+    fn rejects_invalid_windows() {
+        assert_eq!(
+            plan_window(&parse_expr("TUMBLE(ts, INTERVAL '0' SECOND)")),
+            Err("TUMBLE() needs a positive INTERVAL literal".to_string())
+        );
+        assert_eq!(
+            plan_window(&parse_expr("TUMBLE(ts, 1000)")),
+            Err("TUMBLE() needs a positive INTERVAL literal".to_string())
+        );
+        assert_eq!(
+            plan_window(&Expr::FunctionCall(
+                "SESSION".to_string(),
+                vec![ident("ts"), Expr::Interval(u64::MAX, IntervalUnit::Day)],
+            )),
+            Err("the INTERVAL of SESSION() is too long".to_string())
+        );
+        assert_eq!(
+            plan_window(&parse_expr("HOP(ts, INTERVAL '1' SECOND)")),
+            Err("HOP() needs to be called with 3 arguments".to_string())
+        );
+        assert_eq!(
+            plan(
+                "SELECT COUNT(id) AS clicks FROM (VALUES (1)) v \
+                 GROUP BY TUMBLE(ts, INTERVAL '1' SECOND), SESSION(ts, INTERVAL '1' SECOND);",
+            )
+            .unwrap_err(),
+            "only one window function is allowed in GROUP BY"
+        );
+    }
+
+    #[test]
+    fn rejects_columns_outside_of_the_grouping_keys() {
+        assert_eq!(
+            plan("SELECT user_id, COUNT(id) AS clicks FROM (VALUES (1)) v;").unwrap_err(),
+            "user_id must appear in GROUP BY or be used in an aggregate function"
+        );
+        assert_eq!(
+            plan("SELECT name AS name FROM (VALUES (1)) v GROUP BY lower(name);").unwrap_err(),
+            "name must appear in GROUP BY or be used in an aggregate function"
+        );
+    }
+
+    #[test]
+    fn rejects_misplaced_aggregate_calls() {
+        assert_eq!(
+            plan("SELECT user_id FROM (VALUES (1)) v GROUP BY user_id, COUNT(id);").unwrap_err(),
+            "aggregate functions are not allowed in GROUP BY"
+        );
+        assert_eq!(
+            plan("SELECT SUM(COUNT(id)) AS total FROM (VALUES (1)) v;").unwrap_err(),
+            "aggregate function calls cannot be nested in SUM()"
+        );
+        assert_eq!(
+            plan("SELECT SUM(a, b) AS total FROM (VALUES (1)) v;").unwrap_err(),
+            "SUM() needs to be called with 1 argument"
+        );
+    }
+
+    fn names(name: &str) -> Vec<String> {
+        vec![name.to_string()]
+    }
+
+    fn time_bounds(condition: &str) -> (Option<TimeBounds>, Option<Expr>) {
+        plan_time_bounds(&parse_expr(condition), &names("a"), &names("b"))
+    }
+
+    fn bounds(condition: &str) -> Option<(i64, i64)> {
+        time_bounds(condition)
+            .0
+            .map(|time_bounds| (time_bounds.lower, time_bounds.upper))
+    }
+
+    #[test]
+    fn splits_equalities_between_the_sides_into_join_keys() {
+        let (left_keys, right_keys, condition) = plan_join_condition(
+            &parse_expr("a.id = b.id AND b.user = lower(a.user) AND a.x > 1 AND a.y = b.y + a.z"),
+            &names("a"),
+            &names("b"),
+        );
+
+        // Keys are put on the side their relation is on, whichever side of `=` they are.
+        assert_eq!(left_keys, [ident("a.id"), parse_expr("lower(a.user)")]);
+        assert_eq!(right_keys, [ident("b.id"), ident("b.user")]);
+        assert_eq!(condition, Some(parse_expr("a.x > 1 AND a.y = b.y + a.z")));
+    }
+
+    #[test]
+    fn keeps_other_equalities_as_conditions() {
+        let (left_keys, right_keys, condition) = plan_join_condition(
+            &parse_expr("id = b.id AND a.id = a.other AND a.id = 1 OR a.id = b.id"),
+            &names("a"),
+            &names("b"),
+        );
+
+        // Unqualified columns could be on either side, and disjunctions are not split.
+        assert!(left_keys.is_empty());
+        assert!(right_keys.is_empty());
+        assert_eq!(
+            condition,
+            Some(parse_expr(
+                "id = b.id AND a.id = a.other AND a.id = 1 OR a.id = b.id"
+            ))
+        );
+    }
+
+    #[test]
+    fn derives_time_bounds_from_between() {
+        let (time_bounds, condition) =
+            time_bounds("b.ts BETWEEN a.ts AND a.ts + INTERVAL '10' MINUTE AND a.id < b.id");
+
+        assert_eq!(
+            time_bounds,
+            Some(TimeBounds {
+                left_time: ident("a.ts"),
+                right_time: ident("b.ts"),
+                lower: 0,
+                upper: 600_000,
+            })
+        );
+        assert_eq!(condition, Some(parse_expr("a.id < b.id")));
+    }
+
+    #[test]
+    fn derives_time_bounds_from_comparisons() {
+        // `>` and `<` are made inclusive.
+        assert_eq!(
+            bounds("b.ts > a.ts - INTERVAL '1' SECOND AND b.ts < a.ts + INTERVAL '1' SECOND"),
+            Some((-999, 999))
+        );
+        // Comparisons written from the left side are turned around.
+        assert_eq!(
+            bounds("a.ts <= b.ts AND a.ts + INTERVAL '5' SECOND >= b.ts"),
+            Some((0, 5_000))
+        );
+        // The tightest of several bounds is kept.
+        assert_eq!(
+            bounds(
+                "b.ts >= a.ts AND b.ts >= a.ts + INTERVAL '1' SECOND \
+                 AND b.ts <= a.ts + INTERVAL '3' SECOND AND b.ts <= a.ts + INTERVAL '2' SECOND"
+            ),
+            Some((1_000, 2_000))
+        );
+    }
+
+    #[test]
+    fn needs_both_time_bounds() {
+        let condition = "b.ts >= a.ts AND a.id = b.id";
+
+        assert_eq!(time_bounds(condition), (None, Some(parse_expr(condition))));
+        // Bounds between different times cannot be combined.
+        assert_eq!(
+            bounds("b.ts >= a.ts AND b.other_ts <= a.ts + INTERVAL '1' SECOND"),
+            None
+        );
+        assert_eq!(bounds("b.ts >= a.ts AND b.ts <= b.ts"), None);
+    }
+
+    #[test]
+    fn keeps_bounds_that_would_overflow_as_conditions() {
+        let (time_bounds, condition) = plan_time_bounds(
+            &Expr::BinaryOperation(
+                Box::new(parse_expr("b.ts >= a.ts AND b.ts <= a.ts")),
+                BinaryOperator::And,
+                // `b.ts > a.ts + INTERVAL` with the longest interval, whose inclusive bound is
+                // one past the largest difference.
+                Box::new(Expr::BinaryOperation(
+                    Box::new(ident("b.ts")),
+                    BinaryOperator::Gt,
+                    Box::new(Expr::BinaryOperation(
+                        Box::new(ident("a.ts")),
+                        BinaryOperator::Add,
+                        Box::new(Expr::Interval(i64::MAX as u64, IntervalUnit::Millisecond)),
+                    )),
+                )),
+            ),
+            &names("a"),
+            &names("b"),
+        );
+
+        assert_eq!(
+            time_bounds.map(|time_bounds| (time_bounds.lower, time_bounds.upper)),
+            Some((0, 0))
+        );
+        assert!(matches!(
+            condition,
+            Some(Expr::BinaryOperation(_, BinaryOperator::Gt, _))
+        ));
+    }
+
+    #[test]
+    fn plans_an_interval_join() {
+        let mut catalog = new_catalog();
+
+        for name in ["a", "b"] {
+            catalog
+                .relations
+                .insert(name.to_string(), RelationDefinition::Stdin);
+        }
+
+        let query = parse_query(
+            "SELECT a.id AS id FROM a LEFT JOIN b \
+             ON a.id = b.id AND b.ts BETWEEN a.ts AND a.ts + INTERVAL '1' SECOND;",
+        );
+
+        let QueryPlan::Projection { query: plan, .. } = plan_query(&catalog, &query).unwrap()
+        else {
+            panic!("expected a projection");
+        };
+
+        let QueryPlan::Join {
+            join_type,
+            left_keys,
+            right_keys,
+            time_bounds,
+            condition,
+            ..
+        } = *plan
+        else {
+            panic!("expected a join, not {plan:?}");
+        };
+
+        assert_eq!(join_type, JoinType::Left);
+        assert_eq!(left_keys, [ident("a.id")]);
+        assert_eq!(right_keys, [ident("b.id")]);
+        assert_eq!(
+            time_bounds.map(|time_bounds| (time_bounds.lower, time_bounds.upper)),
+            Some((0, 1_000))
+        );
+        assert_eq!(condition, None);
     }
 }
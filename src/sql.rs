@@ -1,13 +1,10 @@
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take_while1},
-    character::{
-        complete::{multispace0, multispace1},
-        is_alphabetic,
-    },
-    combinator::{eof, opt},
-    multi::{many1, separated_list1},
-    sequence::{delimited, preceded, terminated},
+    bytes::complete::{tag, tag_no_case, take_while, take_while1},
+    character::complete::{multispace0, multispace1, satisfy},
+    combinator::{eof, fail, map, map_res, not, opt, peek, recognize, verify},
+    multi::{fold_many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
 use nom_locate::LocatedSpan;
@@ -16,15 +13,146 @@ use nom_recursive::{recursive_parser, RecursiveInfo};
 #[derive(Debug, Clone)]
 pub enum Statement {
     Select(Query),
-    CreateKinesisStream(String, String, String),
+    /// `INSERT INTO relation [PARTITION BY expr] SELECT ...`, where `expr` is evaluated on each
+    /// output record to give its partition key.
+    Insert(String, Option<Expr>, Query),
+    /// `CREATE KINESIS STREAM relation 'stream' [[CONSUMER] 'consumer' [IF NOT EXISTS]]
+    /// [FORMAT ...] [ON ERROR ...] [WITH (...)] [WATERMARK FOR ...]`
+    CreateKinesisStream(
+        String,
+        String,
+        Option<KinesisStreamConsumer>,
+        Format,
+        ErrorPolicy,
+        Options,
+        Option<Watermark>,
+    ),
+    /// `CREATE KAFKA TOPIC relation 'topic' [FORMAT ...] [ON ERROR ...] [WITH (...)]
+    /// [WATERMARK FOR ...]`
+    CreateKafkaTopic(
+        String,
+        String,
+        Format,
+        ErrorPolicy,
+        Options,
+        Option<Watermark>,
+    ),
+    /// `CREATE FILE SINK relation 'path' FORMAT JSON|CSV [COLUMNS (...)]|PARQUET [WITH (...)]`
+    CreateFileSink(String, String, FileSinkFormat, Options),
+    /// `SET key = value`
+    Set(String, String),
+    /// `DROP KINESIS STREAM relation`
+    DropKinesisStream(String),
+    /// `CREATE FILE SOURCE relation 'path' FORMAT ... [ON ERROR ...] [TAIL] [WATERMARK FOR ...]`
+    CreateFileSource(String, String, Format, ErrorPolicy, bool, Option<Watermark>),
+    /// `CREATE SOCKET SOURCE relation TCP|UDP 'address' [FORMAT ...] [ON ERROR ...]
+    /// [WATERMARK FOR ...]`
+    CreateSocketSource(
+        String,
+        SocketProtocol,
+        String,
+        Format,
+        ErrorPolicy,
+        Option<Watermark>,
+    ),
+    /// `CREATE HTTP SOURCE relation 'address' [FORMAT ...] [ON ERROR ...] [WATERMARK FOR ...]`
+    CreateHttpSource(String, String, Format, ErrorPolicy, Option<Watermark>),
+    /// `CREATE GENERATOR SOURCE relation WITH (...) [WATERMARK FOR ...]`
+    CreateGeneratorSource(String, Options, Option<Watermark>),
     Explain(Query),
 }
 
+/// The enhanced fan-out consumer of a Kinesis stream, which is registered first if it does
+/// not exist yet with IF NOT EXISTS.
+#[derive(Debug, Clone)]
+pub struct KinesisStreamConsumer {
+    pub name: String,
+    pub if_not_exists: bool,
+}
+
+/// The `WITH (key = value, ...)` clause of a CREATE statement, with keys in lowercase.
+pub type Options = Vec<(String, String)>;
+
+/// `FORMAT encoding [COMPRESSION GZIP|ZSTD]`: how each payload of a relation, a line of a
+/// file or the data of a Kinesis record, is decoded into records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub compression: Option<Compression>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// `JSON`: a JSON object.
+    #[default]
+    Json,
+    /// `CSV [COLUMNS (column, ...)]`: lines of fields, named by the columns, or else by the
+    /// fields of the first line read.
+    Csv(Option<Vec<String>>),
+    /// `AVRO SCHEMA 'path'`: an Avro datum written with the schema in the file.
+    Avro(String),
+    /// `PROTOBUF MESSAGE 'package.Message' DESCRIPTORS 'path'`: a protobuf message of the
+    /// type, which is described in the file descriptor set in the file.
+    Protobuf(String, String),
+    /// `TEXT`: UTF-8 text, as a single `data` column.
+    Text,
+    /// `BYTES`: anything, base64-encoded as a single `data` column.
+    Bytes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// How a socket source receives payloads: the lines sent over TCP connections, or UDP
+/// datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketProtocol {
+    Tcp,
+    Udp,
+}
+
+/// How a file sink writes records: one JSON object per line, CSV with a header line naming the
+/// columns, by default those of the first record, or Parquet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSinkFormat {
+    Json,
+    Csv(Option<Vec<String>>),
+    Parquet,
+}
+
+/// `ON ERROR SKIP | FAIL | DEAD LETTER 'path' | DEAD LETTER relation`: what happens to a
+/// payload of a relation that cannot be decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// The payload is logged to stderr and left out.
+    #[default]
+    Skip,
+    /// The query fails.
+    Fail,
+    /// The payload is appended to the file as a JSON line, with the error and where the payload
+    /// was read from.
+    DeadLetterFile(String),
+    /// The same dead letter is written to the Kinesis stream or Kafka topic of the relation.
+    DeadLetterRelation(String),
+}
+
+/// `WATERMARK FOR column AS expr`: after each record, `expr` evaluated with `column` as
+/// milliseconds since the epoch gives the event time that later records are expected to reach.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Watermark {
+    pub column: String,
+    pub expr: Expr,
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Query {
     pub select_items: Vec<SelectItem>,
     pub from_items: Vec<FromItem>,
     pub where_condition: Option<Expr>,
+    pub group_by: Vec<Expr>,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -40,6 +168,37 @@ pub enum Expr {
     String(String),
     Number(serde_json::Number),
     BinaryOperation(Box<Expr>, BinaryOperator, Box<Expr>),
+    UnaryOperation(UnaryOperator, Box<Expr>),
+    Interval(u64, IntervalUnit),
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum IntervalUnit {
+    Millisecond,
+    Second,
+    Minute,
+    Hour,
+    Day,
+}
+
+impl IntervalUnit {
+    pub fn milliseconds(&self) -> u64 {
+        match self {
+            IntervalUnit::Millisecond => 1,
+            IntervalUnit::Second => 1_000,
+            IntervalUnit::Minute => 60_000,
+            IntervalUnit::Hour => 3_600_000,
+            IntervalUnit::Day => 86_400_000,
+        }
+    }
+
+    /// The length of `quantity` units in milliseconds, or `None` if it is too long to be added
+    /// to an event time.
+    pub fn interval_milliseconds(&self, quantity: u64) -> Option<i64> {
+        quantity
+            .checked_mul(self.milliseconds())
+            .and_then(|milliseconds| i64::try_from(milliseconds).ok())
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -53,15 +212,64 @@ pub enum BinaryOperator {
     Gt,
     Lt,
     Eq,
+    And,
+    Or,
+}
+
+impl BinaryOperator {
+    /// Left and right binding power; higher binds tighter. Left-associative operators bind
+    /// slightly tighter on the right so that `a - b - c` parses as `(a - b) - c`.
+    pub fn binding_power(&self) -> (u8, u8) {
+        match self {
+            BinaryOperator::Or => (1, 2),
+            BinaryOperator::And => (3, 4),
+            BinaryOperator::Eq
+            | BinaryOperator::Gte
+            | BinaryOperator::Lte
+            | BinaryOperator::Gt
+            | BinaryOperator::Lt => (7, 8),
+            BinaryOperator::Add | BinaryOperator::Sub => (9, 10),
+            BinaryOperator::Mul | BinaryOperator::Div => (11, 12),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Not,
+}
+
+impl UnaryOperator {
+    pub fn binding_power(&self) -> u8 {
+        match self {
+            UnaryOperator::Not => 5,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum FromItem {
     SubQuery(Query),
-    Ident(String),
+    /// A relation from the catalog, with the options of its `WITH (...)` clause in the query.
+    Ident(String, Options),
     Values(Vec<Vec<Expr>>),
+    Aliased(Box<FromItem>, String),
+    Join(Box<FromItem>, JoinType, Box<FromItem>, Expr),
 }
 
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// Keywords that may follow a relation in FROM and so cannot be used as a bare alias.
+const RESERVED_KEYWORDS: [&str; 11] = [
+    "WHERE", "GROUP", "JOIN", "INNER", "LEFT", "RIGHT", "FULL", "OUTER", "ON", "AS", "WITH",
+];
+
 type Span<'a> = LocatedSpan<&'a str, RecursiveInfo>;
 
 pub fn parse_statements(input: Span) -> IResult<Span, Vec<Statement>> {
@@ -76,18 +284,131 @@ pub fn parse_statement(input: Span) -> IResult<Span, Statement> {
     let (input, statement) = terminated(
         alt((
             |input| {
-                let (input, (relation_ident, kinesis_stream_name, kinesis_stream_consumer_name)) =
-                    parse_create_kinesis_stream(input)?;
+                let (
+                    input,
+                    (
+                        relation_ident,
+                        kinesis_stream_name,
+                        kinesis_stream_consumer,
+                        format,
+                        error_policy,
+                        options,
+                        watermark,
+                    ),
+                ) = parse_create_kinesis_stream(input)?;
 
                 Ok((
                     input,
                     Statement::CreateKinesisStream(
                         relation_ident,
                         kinesis_stream_name,
-                        kinesis_stream_consumer_name,
+                        kinesis_stream_consumer,
+                        format,
+                        error_policy,
+                        options,
+                        watermark,
                     ),
                 ))
             },
+            |input| {
+                let (input, (relation_ident, topic, format, error_policy, options, watermark)) =
+                    parse_create_kafka_topic(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateKafkaTopic(
+                        relation_ident,
+                        topic,
+                        format,
+                        error_policy,
+                        options,
+                        watermark,
+                    ),
+                ))
+            },
+            |input| {
+                let (input, (relation_ident, path, format, error_policy, tail, watermark)) =
+                    parse_create_file_source(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateFileSource(
+                        relation_ident,
+                        path,
+                        format,
+                        error_policy,
+                        tail,
+                        watermark,
+                    ),
+                ))
+            },
+            |input| {
+                let (input, (relation_ident, protocol, address, format, error_policy, watermark)) =
+                    parse_create_socket_source(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateSocketSource(
+                        relation_ident,
+                        protocol,
+                        address,
+                        format,
+                        error_policy,
+                        watermark,
+                    ),
+                ))
+            },
+            |input| {
+                let (input, (relation_ident, address, format, error_policy, watermark)) =
+                    parse_create_http_source(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateHttpSource(
+                        relation_ident,
+                        address,
+                        format,
+                        error_policy,
+                        watermark,
+                    ),
+                ))
+            },
+            |input| {
+                let (input, (relation_ident, options, watermark)) =
+                    parse_create_generator_source(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateGeneratorSource(relation_ident, options, watermark),
+                ))
+            },
+            |input| {
+                let (input, (relation_ident, path, format, options)) =
+                    parse_create_file_sink(input)?;
+
+                Ok((
+                    input,
+                    Statement::CreateFileSink(relation_ident, path, format, options),
+                ))
+            },
+            |input| {
+                let (input, (key, value)) = parse_set(input)?;
+
+                Ok((input, Statement::Set(key, value)))
+            },
+            |input| {
+                let (input, relation_ident) = parse_drop_kinesis_stream(input)?;
+
+                Ok((input, Statement::DropKinesisStream(relation_ident)))
+            },
+            |input| {
+                let (input, (relation_ident, partition_key, query)) = parse_insert(input)?;
+
+                Ok((
+                    input,
+                    Statement::Insert(relation_ident, partition_key, query),
+                ))
+            },
             |input| {
                 let (input, _) = tag_no_case("EXPLAIN")(input)?;
                 let (input, _) = multispace0(input)?;
@@ -104,7 +425,7 @@ pub fn parse_statement(input: Span) -> IResult<Span, Statement> {
         parse_statement_terminator,
     )(input)?;
 
-    return Ok((input, statement));
+    Ok((input, statement))
 }
 
 #[recursive_parser]
@@ -129,12 +450,22 @@ fn parse_query(s: Span) -> IResult<Span, Query> {
         parse_expr(input)
     })(input)?;
 
+    let (input, group_by) = opt(|input| {
+        let (input, _) = multispace0(input)?;
+        let (input, _) = parse_keyword("GROUP")(input)?;
+        let (input, _) = multispace1(input)?;
+        let (input, _) = parse_keyword("BY")(input)?;
+        let (input, _) = multispace0(input)?;
+        separated_list1(delimited(multispace0, tag(","), multispace0), parse_expr)(input)
+    })(input)?;
+
     Ok((
         input,
         Query {
             select_items,
             from_items,
             where_condition,
+            group_by: group_by.unwrap_or_default(),
         },
     ))
 }
@@ -143,36 +474,121 @@ fn parse_from_items(input: Span) -> IResult<Span, Vec<FromItem>> {
     let (input, _) = tag_no_case("FROM")(input)?;
     let (input, _) = multispace1(input)?;
     separated_list1(
-        tag(","),
+        delimited(multispace0, tag(","), multispace0),
+        parse_joined_from_item,
+    )(input)
+}
+
+fn parse_joined_from_item(input: Span) -> IResult<Span, FromItem> {
+    let (input, first_from_item) = parse_aliased_from_item(input)?;
+
+    fold_many0(
+        |input| {
+            let (input, _) = multispace1(input)?;
+            let (input, join_type) = parse_join_type(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, from_item) = parse_aliased_from_item(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("ON")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, condition) = parse_expr(input)?;
+
+            Ok((input, (join_type, from_item, condition)))
+        },
+        move || first_from_item.clone(),
+        |left_from_item, (join_type, right_from_item, condition)| {
+            FromItem::Join(
+                Box::new(left_from_item),
+                join_type,
+                Box::new(right_from_item),
+                condition,
+            )
+        },
+    )(input)
+}
+
+fn parse_join_type(input: Span) -> IResult<Span, JoinType> {
+    let (input, join_type) = opt(terminated(
         alt((
-            delimited(
-                tag("("),
-                |input| {
-                    let (input, _) = tag_no_case("VALUES")(input)?;
-                    let (input, _) = multispace1(input)?;
-                    let (input, values) = separated_list1(tag(","), |input| {
-                        let (input, columns) =
-                            delimited(tag("("), separated_list1(tag(","), parse_expr), tag(")"))(
-                                input,
-                            )?;
-
-                        Ok((input, columns))
-                    })(input)?;
-
-                    Ok((input, FromItem::Values(values)))
-                },
-                tag(")"),
-            ),
             |input| {
-                let (input, ident) = parse_ident(input)?;
-                Ok((input, FromItem::Ident(ident)))
+                let (input, _) = parse_keyword("INNER")(input)?;
+                Ok((input, JoinType::Inner))
             },
             |input| {
-                let (input, query) = delimited(tag("("), parse_query, tag(")"))(input)?;
-                Ok((input, FromItem::SubQuery(query)))
+                let (input, join_type) = alt((
+                    |input| {
+                        let (input, _) = parse_keyword("LEFT")(input)?;
+                        Ok((input, JoinType::Left))
+                    },
+                    |input| {
+                        let (input, _) = parse_keyword("RIGHT")(input)?;
+                        Ok((input, JoinType::Right))
+                    },
+                    |input| {
+                        let (input, _) = parse_keyword("FULL")(input)?;
+                        Ok((input, JoinType::Full))
+                    },
+                ))(input)?;
+                let (input, _) = opt(preceded(multispace1, parse_keyword("OUTER")))(input)?;
+                Ok((input, join_type))
             },
         )),
-    )(input)
+        multispace1,
+    ))(input)?;
+    let (input, _) = parse_keyword("JOIN")(input)?;
+
+    Ok((input, join_type.unwrap_or(JoinType::Inner)))
+}
+
+fn parse_aliased_from_item(input: Span) -> IResult<Span, FromItem> {
+    let (input, from_item) = parse_from_item(input)?;
+
+    let (input, alias) = opt(|input| {
+        let (input, _) = multispace1(input)?;
+        let (input, _) = opt(terminated(parse_keyword("AS"), multispace1))(input)?;
+        verify(parse_ident, |ident: &String| {
+            !RESERVED_KEYWORDS
+                .iter()
+                .any(|keyword| keyword.eq_ignore_ascii_case(ident))
+        })(input)
+    })(input)?;
+
+    match alias {
+        Some(alias) => Ok((input, FromItem::Aliased(Box::new(from_item), alias))),
+        None => Ok((input, from_item)),
+    }
+}
+
+fn parse_from_item(input: Span) -> IResult<Span, FromItem> {
+    alt((
+        delimited(
+            tag("("),
+            |input| {
+                let (input, _) = tag_no_case("VALUES")(input)?;
+                let (input, _) = multispace1(input)?;
+                let (input, values) = separated_list1(tag(","), |input| {
+                    let (input, columns) =
+                        delimited(tag("("), separated_list1(tag(","), parse_expr), tag(")"))(
+                            input,
+                        )?;
+
+                    Ok((input, columns))
+                })(input)?;
+
+                Ok((input, FromItem::Values(values)))
+            },
+            tag(")"),
+        ),
+        |input| {
+            let (input, ident) = parse_ident(input)?;
+            let (input, options) = opt(preceded(multispace1, parse_options))(input)?;
+            Ok((input, FromItem::Ident(ident, options.unwrap_or_default())))
+        },
+        |input| {
+            let (input, query) = delimited(tag("("), parse_query, tag(")"))(input)?;
+            Ok((input, FromItem::SubQuery(query)))
+        },
+    ))(input)
 }
 
 fn parse_select_item(input: Span) -> IResult<Span, SelectItem> {
@@ -195,14 +611,90 @@ fn parse_select_item(input: Span) -> IResult<Span, SelectItem> {
 }
 
 fn parse_expr(input: Span) -> IResult<Span, Expr> {
+    parse_expr_with_binding_power(input, 0)
+}
+
+/// Precedence-climbing expression parser. Binary operators only bind their right-hand side
+/// while their left binding power is at least `min_binding_power`, see
+/// [`BinaryOperator::binding_power`].
+fn parse_expr_with_binding_power(input: Span, min_binding_power: u8) -> IResult<Span, Expr> {
+    let (mut input, mut left_expr) = parse_prefix_expr(input)?;
+
+    loop {
+        // `x BETWEEN low AND high` binds like a comparison and is read as
+        // `x >= low AND x <= high`.
+        if let Ok((rest, _)) = preceded(multispace1, parse_keyword("BETWEEN"))(input) {
+            let (left_binding_power, right_binding_power) = BinaryOperator::Gte.binding_power();
+
+            if left_binding_power < min_binding_power {
+                break;
+            }
+
+            let (rest, _) = multispace1(rest)?;
+            let (rest, low_expr) = parse_expr_with_binding_power(rest, right_binding_power)?;
+            let (rest, _) = delimited(multispace1, parse_keyword("AND"), multispace1)(rest)?;
+            let (rest, high_expr) = parse_expr_with_binding_power(rest, right_binding_power)?;
+
+            input = rest;
+            left_expr = Expr::BinaryOperation(
+                Box::new(Expr::BinaryOperation(
+                    Box::new(left_expr.clone()),
+                    BinaryOperator::Gte,
+                    Box::new(low_expr),
+                )),
+                BinaryOperator::And,
+                Box::new(Expr::BinaryOperation(
+                    Box::new(left_expr),
+                    BinaryOperator::Lte,
+                    Box::new(high_expr),
+                )),
+            );
+            continue;
+        }
+
+        let Ok((rest, binary_operator)) = preceded(multispace0, parse_binary_operator)(input)
+        else {
+            break;
+        };
+
+        let (left_binding_power, right_binding_power) = binary_operator.binding_power();
+
+        if left_binding_power < min_binding_power {
+            break;
+        }
+
+        let (rest, _) = multispace0(rest)?;
+        let (rest, right_expr) = parse_expr_with_binding_power(rest, right_binding_power)?;
+
+        input = rest;
+        left_expr =
+            Expr::BinaryOperation(Box::new(left_expr), binary_operator, Box::new(right_expr));
+    }
+
+    Ok((input, left_expr))
+}
+
+fn parse_prefix_expr(input: Span) -> IResult<Span, Expr> {
     alt((
         |input| {
-            let (input, (left_expr, binary_operator, right_expr)) = parse_binary_operation(input)?;
+            let (input, _) = terminated(parse_keyword("NOT"), multispace0)(input)?;
+            let (input, expr) =
+                parse_expr_with_binding_power(input, UnaryOperator::Not.binding_power())?;
+
             Ok((
                 input,
-                Expr::BinaryOperation(Box::new(left_expr), binary_operator, Box::new(right_expr)),
+                Expr::UnaryOperation(UnaryOperator::Not, Box::new(expr)),
             ))
         },
+        delimited(
+            terminated(tag("("), multispace0),
+            parse_expr,
+            preceded(multispace0, tag(")")),
+        ),
+        |input| {
+            let (input, (quantity, unit)) = parse_interval(input)?;
+            Ok((input, Expr::Interval(quantity, unit)))
+        },
         |input| {
             let (input, (ident, parsed_exprs)) = parse_function_call(input)?;
             Ok((input, Expr::FunctionCall(ident, parsed_exprs)))
@@ -216,16 +708,35 @@ fn parse_expr(input: Span) -> IResult<Span, Expr> {
             Ok((input, Expr::Number(number)))
         },
         |input| {
-            let (input, ident) = parse_ident(input)?;
+            let (input, ident) = parse_column_ref(input)?;
             Ok((input, Expr::Ident(ident)))
         },
     ))(input)
 }
 
+/// A column, optionally qualified by the relation it comes from, as in `clicks.user_id`.
+fn parse_column_ref(input: Span) -> IResult<Span, String> {
+    let (input, column_ref) =
+        recognize(pair(parse_ident, opt(pair(tag("."), parse_ident))))(input)?;
+
+    Ok((input, column_ref.to_string()))
+}
+
+fn parse_keyword<'a>(keyword: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Span<'a>> {
+    terminated(tag_no_case(keyword), not(peek(satisfy(is_ident_char))))
+}
+
 fn parse_ident(input: Span) -> IResult<Span, String> {
-    let (input, ident) = take_while1(|ch: char| is_alphabetic(ch as u8) || ch == '_')(input)?;
+    let (input, ident) = recognize(pair(
+        satisfy(|ch: char| ch.is_ascii_alphabetic() || ch == '_'),
+        take_while(is_ident_char),
+    ))(input)?;
 
-    return Ok((input, ident.to_string()));
+    Ok((input, ident.to_string()))
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
 }
 
 fn parse_function_call(input: Span) -> IResult<Span, (String, Vec<Expr>)> {
@@ -234,51 +745,512 @@ fn parse_function_call(input: Span) -> IResult<Span, (String, Vec<Expr>)> {
     let (input, parsed_exprs) = preceded(
         tag("("),
         terminated(
-            opt(separated_list1(
-                delimited(opt(multispace0), tag(","), opt(multispace0)),
-                parse_expr,
+            alt((
+                // `count(*)` is treated the same as calling the function without arguments.
+                |input| {
+                    let (input, _) = delimited(multispace0, tag("*"), multispace0)(input)?;
+                    Ok((input, None))
+                },
+                opt(separated_list1(
+                    delimited(opt(multispace0), tag(","), opt(multispace0)),
+                    parse_expr,
+                )),
             )),
             tag(")"),
         ),
     )(input)?;
 
+    IResult::Ok((input, (ident, parsed_exprs.unwrap_or_default())))
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_create_kinesis_stream(
+    input: Span,
+) -> IResult<
+    Span,
+    (
+        String,
+        String,
+        Option<KinesisStreamConsumer>,
+        Format,
+        ErrorPolicy,
+        Options,
+        Option<Watermark>,
+    ),
+> {
+    let (input, _) = tag_no_case("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = tag_no_case("KINESIS")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = tag_no_case("STREAM")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, kinesis_stream_name) = parse_string(input)?;
+
+    // Streams read by polling have no consumer.
+    let (input, kinesis_stream_consumer) = opt(preceded(multispace1, |input| {
+        let (input, _) = opt(terminated(parse_keyword("CONSUMER"), multispace1))(input)?;
+        let (input, name) = parse_string(input)?;
+        let (input, if_not_exists) = opt(|input| {
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("IF")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("NOT")(input)?;
+            let (input, _) = multispace1(input)?;
+            parse_keyword("EXISTS")(input)
+        })(input)?;
+
+        IResult::Ok((
+            input,
+            KinesisStreamConsumer {
+                name,
+                if_not_exists: if_not_exists.is_some(),
+            },
+        ))
+    }))(input)?;
+
+    let (input, format) = opt(preceded(multispace1, parse_format))(input)?;
+
+    let (input, error_policy) = opt(preceded(multispace1, parse_error_policy))(input)?;
+
+    let (input, options) = opt(preceded(multispace1, parse_options))(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
+
     IResult::Ok((
         input,
         (
-            ident,
-            if let Some(parsed_exprs) = parsed_exprs {
-                parsed_exprs
-            } else {
-                vec![]
-            },
+            relation_ident,
+            kinesis_stream_name,
+            kinesis_stream_consumer,
+            format.unwrap_or_default(),
+            error_policy.unwrap_or_default(),
+            options.unwrap_or_default(),
+            watermark,
         ),
     ))
 }
 
-fn parse_create_kinesis_stream(input: Span) -> IResult<Span, (String, String, String)> {
-    let (input, _) = tag_no_case("CREATE")(input)?;
+#[allow(clippy::type_complexity)]
+fn parse_create_kafka_topic(
+    input: Span,
+) -> IResult<
+    Span,
+    (
+        String,
+        String,
+        Format,
+        ErrorPolicy,
+        Options,
+        Option<Watermark>,
+    ),
+> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
     let (input, _) = multispace1(input)?;
-    let (input, _) = tag_no_case("KINESIS")(input)?;
+    let (input, _) = parse_keyword("KAFKA")(input)?;
     let (input, _) = multispace1(input)?;
-    let (input, _) = tag_no_case("STREAM")(input)?;
+    let (input, _) = parse_keyword("TOPIC")(input)?;
     let (input, _) = multispace1(input)?;
 
     let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
 
-    let (input, kinesis_stream_name) = terminated(parse_string, multispace1)(input)?;
+    let (input, topic) = parse_string(input)?;
 
-    let (input, kinesis_stream_consumer_name) = parse_string(input)?;
+    let (input, format) = opt(preceded(multispace1, parse_format))(input)?;
+
+    let (input, error_policy) = opt(preceded(multispace1, parse_error_policy))(input)?;
+
+    let (input, options) = opt(preceded(multispace1, parse_options))(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
 
     IResult::Ok((
         input,
         (
             relation_ident,
-            kinesis_stream_name,
-            kinesis_stream_consumer_name,
+            topic,
+            format.unwrap_or_default(),
+            error_policy.unwrap_or_default(),
+            options.unwrap_or_default(),
+            watermark,
         ),
     ))
 }
 
+fn parse_insert(input: Span) -> IResult<Span, (String, Option<Expr>, Query)> {
+    let (input, _) = parse_keyword("INSERT")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("INTO")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, partition_key) = opt(|input| {
+        let (input, _) = parse_keyword("PARTITION")(input)?;
+        let (input, _) = multispace1(input)?;
+        let (input, _) = parse_keyword("BY")(input)?;
+        let (input, _) = multispace1(input)?;
+        terminated(parse_expr, multispace1)(input)
+    })(input)?;
+
+    let (input, query) = parse_query(input)?;
+
+    IResult::Ok((input, (relation_ident, partition_key, query)))
+}
+
+fn parse_drop_kinesis_stream(input: Span) -> IResult<Span, String> {
+    let (input, _) = parse_keyword("DROP")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("KINESIS")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("STREAM")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    parse_ident(input)
+}
+
+/// Parses `WITH (key = value, ...)`, where a value is a string, or a number or word written
+/// without quotes.
+fn parse_options(input: Span) -> IResult<Span, Options> {
+    let (input, _) = parse_keyword("WITH")(input)?;
+    let (input, _) = multispace0(input)?;
+
+    delimited(
+        terminated(tag("("), multispace0),
+        separated_list1(delimited(multispace0, tag(","), multispace0), |input| {
+            let (input, key) = parse_ident(input)?;
+            let (input, _) = delimited(multispace0, tag("="), multispace0)(input)?;
+            let (input, value) = parse_option_value(input)?;
+
+            IResult::Ok((input, (key.to_lowercase(), value)))
+        }),
+        preceded(multispace0, tag(")")),
+    )(input)
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_create_file_source(
+    input: Span,
+) -> IResult<Span, (String, String, Format, ErrorPolicy, bool, Option<Watermark>)> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("FILE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SOURCE")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, path) = terminated(parse_string, multispace1)(input)?;
+
+    let (input, format) = parse_format(input)?;
+
+    let (input, error_policy) = opt(preceded(multispace1, parse_error_policy))(input)?;
+
+    let (input, tail) = opt(preceded(multispace1, parse_keyword("TAIL")))(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
+
+    IResult::Ok((
+        input,
+        (
+            relation_ident,
+            path,
+            format,
+            error_policy.unwrap_or_default(),
+            tail.is_some(),
+            watermark,
+        ),
+    ))
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_create_socket_source(
+    input: Span,
+) -> IResult<
+    Span,
+    (
+        String,
+        SocketProtocol,
+        String,
+        Format,
+        ErrorPolicy,
+        Option<Watermark>,
+    ),
+> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SOCKET")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SOURCE")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, protocol) = terminated(
+        alt((
+            map(parse_keyword("TCP"), |_| SocketProtocol::Tcp),
+            map(parse_keyword("UDP"), |_| SocketProtocol::Udp),
+        )),
+        multispace1,
+    )(input)?;
+
+    let (input, address) = parse_string(input)?;
+
+    let (input, format) = opt(preceded(multispace1, parse_format))(input)?;
+
+    let (input, error_policy) = opt(preceded(multispace1, parse_error_policy))(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
+
+    IResult::Ok((
+        input,
+        (
+            relation_ident,
+            protocol,
+            address,
+            format.unwrap_or_default(),
+            error_policy.unwrap_or_default(),
+            watermark,
+        ),
+    ))
+}
+
+#[allow(clippy::type_complexity)]
+fn parse_create_http_source(
+    input: Span,
+) -> IResult<Span, (String, String, Format, ErrorPolicy, Option<Watermark>)> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("HTTP")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SOURCE")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, address) = parse_string(input)?;
+
+    let (input, format) = opt(preceded(multispace1, parse_format))(input)?;
+
+    let (input, error_policy) = opt(preceded(multispace1, parse_error_policy))(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
+
+    IResult::Ok((
+        input,
+        (
+            relation_ident,
+            address,
+            format.unwrap_or_default(),
+            error_policy.unwrap_or_default(),
+            watermark,
+        ),
+    ))
+}
+
+fn parse_create_generator_source(
+    input: Span,
+) -> IResult<Span, (String, Options, Option<Watermark>)> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("GENERATOR")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SOURCE")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, options) = parse_options(input)?;
+
+    let (input, watermark) = opt(preceded(multispace1, parse_watermark))(input)?;
+
+    IResult::Ok((input, (relation_ident, options, watermark)))
+}
+
+fn parse_format(input: Span) -> IResult<Span, Format> {
+    let (input, _) = parse_keyword("FORMAT")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, encoding) = alt((
+        |input| {
+            let (input, _) = parse_keyword("JSON")(input)?;
+            IResult::Ok((input, Encoding::Json))
+        },
+        |input| {
+            let (input, _) = parse_keyword("CSV")(input)?;
+            let (input, columns) = opt(preceded(multispace1, parse_columns))(input)?;
+            IResult::Ok((input, Encoding::Csv(columns)))
+        },
+        |input| {
+            let (input, _) = parse_keyword("AVRO")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("SCHEMA")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, schema_path) = parse_string(input)?;
+            IResult::Ok((input, Encoding::Avro(schema_path)))
+        },
+        |input| {
+            let (input, _) = parse_keyword("PROTOBUF")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("MESSAGE")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, message_name) = parse_string(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("DESCRIPTORS")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, descriptor_set_path) = parse_string(input)?;
+            IResult::Ok((input, Encoding::Protobuf(message_name, descriptor_set_path)))
+        },
+        |input| {
+            let (input, _) = parse_keyword("TEXT")(input)?;
+            IResult::Ok((input, Encoding::Text))
+        },
+        |input| {
+            let (input, _) = parse_keyword("BYTES")(input)?;
+            IResult::Ok((input, Encoding::Bytes))
+        },
+    ))(input)?;
+
+    let (input, compression) = opt(|input| {
+        let (input, _) = multispace1(input)?;
+        let (input, _) = parse_keyword("COMPRESSION")(input)?;
+        let (input, _) = multispace1(input)?;
+        alt((
+            |input| {
+                let (input, _) = parse_keyword("GZIP")(input)?;
+                IResult::Ok((input, Compression::Gzip))
+            },
+            |input| {
+                let (input, _) = parse_keyword("ZSTD")(input)?;
+                IResult::Ok((input, Compression::Zstd))
+            },
+        ))(input)
+    })(input)?;
+
+    IResult::Ok((
+        input,
+        Format {
+            encoding,
+            compression,
+        },
+    ))
+}
+
+/// Parses `COLUMNS (column, ...)`.
+fn parse_columns(input: Span) -> IResult<Span, Vec<String>> {
+    let (input, _) = parse_keyword("COLUMNS")(input)?;
+    let (input, _) = multispace0(input)?;
+
+    delimited(
+        terminated(tag("("), multispace0),
+        separated_list1(delimited(multispace0, tag(","), multispace0), parse_ident),
+        preceded(multispace0, tag(")")),
+    )(input)
+}
+
+fn parse_create_file_sink(input: Span) -> IResult<Span, (String, String, FileSinkFormat, Options)> {
+    let (input, _) = parse_keyword("CREATE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("FILE")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("SINK")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    let (input, relation_ident) = terminated(parse_ident, multispace1)(input)?;
+
+    let (input, path) = terminated(parse_string, multispace1)(input)?;
+
+    let (input, _) = parse_keyword("FORMAT")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, format) = alt((
+        |input| {
+            let (input, _) = parse_keyword("JSON")(input)?;
+            IResult::Ok((input, FileSinkFormat::Json))
+        },
+        |input| {
+            let (input, _) = parse_keyword("CSV")(input)?;
+            let (input, columns) = opt(preceded(multispace1, parse_columns))(input)?;
+            IResult::Ok((input, FileSinkFormat::Csv(columns)))
+        },
+        |input| {
+            let (input, _) = parse_keyword("PARQUET")(input)?;
+            IResult::Ok((input, FileSinkFormat::Parquet))
+        },
+    ))(input)?;
+
+    let (input, options) = opt(preceded(multispace1, parse_options))(input)?;
+
+    IResult::Ok((
+        input,
+        (relation_ident, path, format, options.unwrap_or_default()),
+    ))
+}
+
+/// Parses `SET key = value`, with the key in lowercase.
+fn parse_set(input: Span) -> IResult<Span, (String, String)> {
+    let (input, _) = parse_keyword("SET")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, key) = parse_ident(input)?;
+    let (input, _) = delimited(multispace0, tag("="), multispace0)(input)?;
+    let (input, value) = parse_option_value(input)?;
+
+    IResult::Ok((input, (key.to_lowercase(), value)))
+}
+
+fn parse_error_policy(input: Span) -> IResult<Span, ErrorPolicy> {
+    let (input, _) = parse_keyword("ON")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("ERROR")(input)?;
+    let (input, _) = multispace1(input)?;
+
+    alt((
+        |input| {
+            let (input, _) = parse_keyword("SKIP")(input)?;
+            IResult::Ok((input, ErrorPolicy::Skip))
+        },
+        |input| {
+            let (input, _) = parse_keyword("FAIL")(input)?;
+            IResult::Ok((input, ErrorPolicy::Fail))
+        },
+        |input| {
+            let (input, _) = parse_keyword("DEAD")(input)?;
+            let (input, _) = multispace1(input)?;
+            let (input, _) = parse_keyword("LETTER")(input)?;
+            let (input, _) = multispace1(input)?;
+            alt((
+                map(parse_string, ErrorPolicy::DeadLetterFile),
+                map(parse_ident, ErrorPolicy::DeadLetterRelation),
+            ))(input)
+        },
+    ))(input)
+}
+
+fn parse_option_value(input: Span) -> IResult<Span, String> {
+    alt((
+        parse_string,
+        map(
+            take_while1(|ch: char| is_ident_char(ch) || ch == '.'),
+            |value: Span| value.to_string(),
+        ),
+    ))(input)
+}
+
+fn parse_watermark(input: Span) -> IResult<Span, Watermark> {
+    let (input, _) = parse_keyword("WATERMARK")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("FOR")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, column) = parse_ident(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, _) = parse_keyword("AS")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, expr) = parse_expr(input)?;
+
+    IResult::Ok((input, Watermark { column, expr }))
+}
+
 fn parse_string(input: Span) -> IResult<Span, String> {
     let (input, string) = delimited(tag("'"), take_while1(|chr| chr != '\''), tag("'"))(input)?;
 
@@ -291,19 +1263,64 @@ fn parse_number(input: Span) -> IResult<Span, serde_json::Number> {
     IResult::Ok((input, digits.parse().unwrap()))
 }
 
-#[recursive_parser]
-fn parse_binary_operation(s: Span) -> IResult<Span, (Expr, BinaryOperator, Expr)> {
-    let (s, left_expr) = parse_expr(s)?;
-    let (s, _) = multispace0(s)?;
-    let (s, binary_operator) = parse_binary_operator(s)?;
-    let (s, _) = multispace0(s)?;
-    let (s, right_expr) = parse_expr(s)?;
+/// Parses `INTERVAL '10' MINUTE`; the quantity may also be written without quotes.
+fn parse_interval(input: Span) -> IResult<Span, (u64, IntervalUnit)> {
+    let (input, _) = parse_keyword("INTERVAL")(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, quantity) = alt((
+        delimited(tag("'"), parse_unsigned, tag("'")),
+        parse_unsigned,
+    ))(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, unit) = alt((
+        |input| {
+            let (input, _) =
+                alt((parse_keyword("MILLISECONDS"), parse_keyword("MILLISECOND")))(input)?;
+            Ok((input, IntervalUnit::Millisecond))
+        },
+        |input| {
+            let (input, _) = alt((parse_keyword("SECONDS"), parse_keyword("SECOND")))(input)?;
+            Ok((input, IntervalUnit::Second))
+        },
+        |input| {
+            let (input, _) = alt((parse_keyword("MINUTES"), parse_keyword("MINUTE")))(input)?;
+            Ok((input, IntervalUnit::Minute))
+        },
+        |input| {
+            let (input, _) = alt((parse_keyword("HOURS"), parse_keyword("HOUR")))(input)?;
+            Ok((input, IntervalUnit::Hour))
+        },
+        |input| {
+            let (input, _) = alt((parse_keyword("DAYS"), parse_keyword("DAY")))(input)?;
+            Ok((input, IntervalUnit::Day))
+        },
+    ))(input)?;
+
+    // Intervals are added to event times in milliseconds, so longer ones are rejected.
+    if unit.interval_milliseconds(quantity).is_none() {
+        return fail(input);
+    }
+
+    Ok((input, (quantity, unit)))
+}
 
-    Ok((s, (left_expr, binary_operator, right_expr)))
+fn parse_unsigned(input: Span) -> IResult<Span, u64> {
+    map_res(
+        take_while1(|ch: char| ch.is_ascii_digit()),
+        |digits: Span| digits.parse::<u64>(),
+    )(input)
 }
 
 fn parse_binary_operator(input: Span) -> IResult<Span, BinaryOperator> {
     alt((
+        |input| {
+            let (input, _) = parse_keyword("AND")(input)?;
+            IResult::Ok((input, BinaryOperator::And))
+        },
+        |input| {
+            let (input, _) = parse_keyword("OR")(input)?;
+            IResult::Ok((input, BinaryOperator::Or))
+        },
         |input| {
             let (input, _) = tag("+")(input)?;
             IResult::Ok((input, BinaryOperator::Add))